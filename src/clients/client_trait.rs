@@ -6,7 +6,9 @@ use super::request::Method;
 
 #[async_trait]
 pub trait HttpClient: Send {
-    async fn connect(&mut self, addr: Arc<String>);
+    async fn connect(&mut self, addr: Arc<String>) -> Result<(), Box<dyn Error>>;
+    /// Drops the connection to `addr`, the next request opens a new one.
+    fn disconnect(&mut self, addr: &str);
     async fn request(
         &mut self,
         method: Method,
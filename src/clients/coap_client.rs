@@ -0,0 +1,539 @@
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{net::UdpSocket, time::Instant};
+
+use super::MAX_DATAGRAM;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+pub const GET: u8 = 0x01;
+pub const POST: u8 = 0x02;
+pub const PUT: u8 = 0x03;
+pub const DELETE: u8 = 0x04;
+
+pub const OBSERVE: u16 = 6;
+pub const URI_PATH: u16 = 11;
+pub const CONTENT_FORMAT: u16 = 12;
+pub const URI_QUERY: u16 = 15;
+pub const BLOCK2: u16 = 23;
+pub const BLOCK1: u16 = 27;
+
+/// 2.31 Continue, the answer to every block of a request but the last
+const CONTINUE: u8 = 0x5f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub kind: Type,
+    /// Class in the upper 3 bits, detail in the lower 5, e.g. 2.05 is 0x45
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Vec<u8>,
+    /// Options by number, in the order they are sent
+    pub options: Vec<(u16, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(option, _)| *option == number)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let kind = match self.kind {
+            Type::Confirmable => 0,
+            Type::NonConfirmable => 1,
+            Type::Acknowledgement => 2,
+            Type::Reset => 3,
+        };
+
+        let mut data = vec![VERSION << 6 | kind << 4 | self.token.len() as u8, self.code];
+        data.extend_from_slice(&self.message_id.to_be_bytes());
+        data.extend_from_slice(&self.token);
+
+        let mut options = self.options.clone();
+        options.sort_by_key(|(number, _)| *number);
+
+        let mut last = 0;
+        for (number, value) in options {
+            let (delta, delta_ext) = option_nibble(number - last);
+            let (length, length_ext) = option_nibble(value.len() as u16);
+            data.push(delta << 4 | length);
+            data.extend_from_slice(&delta_ext);
+            data.extend_from_slice(&length_ext);
+            data.extend_from_slice(&value);
+            last = number;
+        }
+
+        if !self.payload.is_empty() {
+            data.push(PAYLOAD_MARKER);
+            data.extend_from_slice(&self.payload);
+        }
+
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 || data[0] >> 6 != VERSION {
+            return None;
+        }
+
+        let kind = match (data[0] >> 4) & 0x03 {
+            0 => Type::Confirmable,
+            1 => Type::NonConfirmable,
+            2 => Type::Acknowledgement,
+            _ => Type::Reset,
+        };
+        let token_length = (data[0] & 0x0f) as usize;
+        let token = data.get(4..4 + token_length)?.to_vec();
+
+        let mut options = Vec::new();
+        let mut payload = Vec::new();
+        let mut number: u16 = 0;
+        let mut rest = &data[4 + token_length..];
+
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == PAYLOAD_MARKER {
+                payload = tail.to_vec();
+                break;
+            }
+
+            rest = tail;
+            let delta = option_value(byte >> 4, &mut rest)?;
+            let length = option_value(byte & 0x0f, &mut rest)? as usize;
+
+            number = number.checked_add(delta)?;
+            options.push((number, rest.get(..length)?.to_vec()));
+            rest = &rest[length..];
+        }
+
+        Some(Self {
+            kind,
+            code: data[1],
+            message_id: u16::from_be_bytes([data[2], data[3]]),
+            token,
+            options,
+            payload,
+        })
+    }
+}
+
+/// A code as it is written, e.g. `2.05`.
+pub fn format_code(code: u8) -> String {
+    format!("{}.{:02}", code >> 5, code & 0x1f)
+}
+
+/// The shortest big-endian encoding of an unsigned option value.
+pub fn encode_uint(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+    bytes[skip..].to_vec()
+}
+
+pub fn decode_uint(value: &[u8]) -> u32 {
+    value
+        .iter()
+        .fold(0, |uint, byte| uint << 8 | *byte as u32)
+}
+
+/// A block option: the block's number, whether more follow, and its size.
+#[derive(Debug, Clone, Copy)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    pub size: usize,
+}
+
+impl Block {
+    pub fn encode(&self) -> Vec<u8> {
+        let szx = self.size.trailing_zeros() - 4;
+        encode_uint(self.num << 4 | (self.more as u32) << 3 | szx)
+    }
+
+    pub fn decode(value: &[u8]) -> Self {
+        let value = decode_uint(value);
+        Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            size: 1 << ((value & 0x07) + 4),
+        }
+    }
+}
+
+fn option_nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+fn option_value(nibble: u8, rest: &mut &[u8]) -> Option<u16> {
+    let (value, used) = match nibble {
+        0..=12 => (nibble as u16, 0),
+        13 => (*rest.first()? as u16 + 13, 1),
+        // Values past u16::MAX can't be decoded
+        14 => {
+            let extended = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+            (extended.checked_add(269)?, 2)
+        }
+        _ => return None,
+    };
+
+    *rest = &rest[used..];
+    Some(value)
+}
+
+#[derive(Debug)]
+pub enum CoapError {
+    /// No answer, after all retransmissions of a confirmable request
+    Timeout,
+    /// The server rejected the message
+    Reset,
+    /// Sending or receiving failed, e.g. the server's port is closed
+    Io,
+}
+
+impl From<io::Error> for CoapError {
+    fn from(_: io::Error) -> Self {
+        CoapError::Io
+    }
+}
+
+/// A response, with how often the request had to be sent again to get it.
+#[derive(Debug)]
+pub struct Response {
+    pub message: Message,
+    pub retransmissions: u32,
+}
+
+/// One endpoint talking to a server over UDP, a request at a time.
+pub struct CoapClient {
+    addr: String,
+    socket: Option<UdpSocket>,
+    /// Datagrams are read into it, one at a time
+    buffer: Vec<u8>,
+    next_message_id: u16,
+    next_token: u32,
+    /// Time a confirmable message waits for its acknowledgement at first,
+    /// doubled with every retransmission
+    ack_timeout: Duration,
+    max_retransmit: u32,
+    /// Time a request waits for its response once acknowledged, or at all
+    /// when it is non-confirmable
+    timeout: Duration,
+}
+
+impl CoapClient {
+    pub fn new(
+        addr: &str,
+        id: usize,
+        ack_timeout: Duration,
+        max_retransmit: u32,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            addr: addr.to_owned(),
+            socket: None,
+            buffer: vec![0; MAX_DATAGRAM],
+            next_message_id: (id as u16).wrapping_mul(7919),
+            next_token: 0,
+            ack_timeout,
+            max_retransmit,
+            timeout,
+        }
+    }
+
+    async fn socket(&mut self) -> io::Result<&UdpSocket> {
+        if self.socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(&self.addr).await?;
+            self.socket = Some(socket);
+        }
+
+        Ok(self.socket.as_ref().unwrap())
+    }
+
+    /// Closes the socket, the next request binds a new one.
+    pub fn close(&mut self) {
+        self.socket = None;
+    }
+
+    pub fn new_token(&mut self) -> Vec<u8> {
+        self.next_token = self.next_token.wrapping_add(1);
+        self.next_token.to_be_bytes().to_vec()
+    }
+
+    fn new_message_id(&mut self) -> u16 {
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        self.next_message_id
+    }
+
+    /// Sends a request and waits for its response. Confirmable requests are
+    /// sent again with exponential back-off until acknowledged.
+    pub async fn request(
+        &mut self,
+        confirmable: bool,
+        code: u8,
+        token: Vec<u8>,
+        options: Vec<(u16, Vec<u8>)>,
+        payload: Vec<u8>,
+    ) -> Result<Response, CoapError> {
+        let request = Message {
+            kind: if confirmable {
+                Type::Confirmable
+            } else {
+                Type::NonConfirmable
+            },
+            code,
+            message_id: self.new_message_id(),
+            token,
+            options,
+            payload,
+        };
+        let data = request.encode();
+
+        let mut retransmissions = 0;
+        let mut ack_timeout = self.ack_timeout.mul_f64(1.0 + jitter() * 0.5);
+        let mut acknowledged = !confirmable;
+        let mut deadline = Instant::now() + if confirmable { ack_timeout } else { self.timeout };
+
+        self.socket().await?.send(&data).await?;
+
+        loop {
+            let message = match self.receive(deadline).await? {
+                Some(message) => message,
+                None if acknowledged || retransmissions == self.max_retransmit => {
+                    return Err(CoapError::Timeout)
+                }
+                None => {
+                    retransmissions += 1;
+                    ack_timeout *= 2;
+                    deadline = Instant::now() + ack_timeout;
+                    self.socket().await?.send(&data).await?;
+                    continue;
+                }
+            };
+
+            let is_answer = message.message_id == request.message_id
+                && matches!(message.kind, Type::Acknowledgement | Type::Reset);
+
+            if is_answer && message.kind == Type::Reset {
+                return Err(CoapError::Reset);
+            }
+
+            if is_answer && message.code == 0 {
+                // Empty acknowledgement, the response follows separately
+                acknowledged = true;
+                deadline = Instant::now() + self.timeout;
+                continue;
+            }
+
+            if message.token == request.token && (is_answer || message.kind != Type::Acknowledgement)
+            {
+                self.acknowledge(&message).await?;
+                return Ok(Response {
+                    message,
+                    retransmissions,
+                });
+            }
+
+            self.reject(&message).await?;
+        }
+    }
+
+    /// Waits for the next message carrying `token`, e.g. a notification.
+    /// `None` once the deadline passed.
+    pub async fn next(&mut self, token: &[u8], deadline: Instant) -> Result<Option<Message>, CoapError> {
+        while let Some(message) = self.receive(deadline).await? {
+            if message.token == token && message.kind != Type::Acknowledgement {
+                self.acknowledge(&message).await?;
+                return Ok(Some(message));
+            }
+
+            self.reject(&message).await?;
+        }
+
+        Ok(None)
+    }
+
+    async fn receive(&mut self, deadline: Instant) -> io::Result<Option<Message>> {
+        self.socket().await?;
+        let socket = self.socket.as_ref().unwrap();
+
+        loop {
+            match tokio::time::timeout_at(deadline, socket.recv(&mut self.buffer)).await {
+                Ok(received) => {
+                    if let Some(message) = Message::decode(&self.buffer[..received?]) {
+                        return Ok(Some(message));
+                    }
+                }
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    /// Acknowledges a confirmable message from the server.
+    async fn acknowledge(&mut self, message: &Message) -> io::Result<()> {
+        self.answer(message, Type::Acknowledgement).await
+    }
+
+    /// Rejects a confirmable message nobody waits for, e.g. a notification
+    /// of a cancelled observation, so the server stops sending it.
+    async fn reject(&mut self, message: &Message) -> io::Result<()> {
+        self.answer(message, Type::Reset).await
+    }
+
+    async fn answer(&mut self, message: &Message, kind: Type) -> io::Result<()> {
+        if message.kind != Type::Confirmable {
+            return Ok(());
+        }
+
+        let answer = Message {
+            kind,
+            code: 0,
+            message_id: message.message_id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        };
+        self.socket().await?.send(&answer.encode()).await?;
+        Ok(())
+    }
+
+    /// Sends a request in blocks of `size` bytes, every block but the last
+    /// answered with 2.31 Continue. Returns the last block's response and the
+    /// retransmissions of all blocks.
+    pub async fn request_blockwise(
+        &mut self,
+        confirmable: bool,
+        code: u8,
+        options: Vec<(u16, Vec<u8>)>,
+        payload: Vec<u8>,
+        size: usize,
+    ) -> Result<(Response, u32), CoapError> {
+        let blocks: Vec<&[u8]> = payload.chunks(size).collect();
+        let mut retransmissions = 0;
+
+        for (num, block) in blocks.iter().enumerate() {
+            let more = num + 1 < blocks.len();
+            let mut block_options = options.clone();
+            block_options.push((
+                BLOCK1,
+                Block {
+                    num: num as u32,
+                    more,
+                    size,
+                }
+                .encode(),
+            ));
+
+            let token = self.new_token();
+            let response = self
+                .request(confirmable, code, token, block_options, block.to_vec())
+                .await?;
+            retransmissions += response.retransmissions;
+
+            if !more || response.message.code != CONTINUE {
+                return Ok((response, retransmissions));
+            }
+        }
+
+        unreachable!("a payload has at least one block")
+    }
+}
+
+/// A fraction in [0, 1) to spread out retransmissions of different clients.
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    (nanos % 1000) as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(options: Vec<(u16, Vec<u8>)>, payload: &[u8]) -> Message {
+        Message {
+            kind: Type::Confirmable,
+            code: GET,
+            message_id: 0x1234,
+            token: vec![0xab],
+            options,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn messages_are_encoded_as_the_rfc_lays_them_out() {
+        let encoded = message(vec![(URI_PATH, b"a".to_vec())], b"hi").encode();
+
+        let expected = [0x41, GET, 0x12, 0x34, 0xab, 0xb1, b'a', PAYLOAD_MARKER, b'h', b'i'];
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn messages_survive_encoding_and_decoding() {
+        let options = vec![
+            (URI_PATH, b"sensors".to_vec()),
+            (URI_PATH, b"temp".to_vec()),
+            (60, vec![1; 20]),
+            (2000, vec![2; 300]),
+        ];
+        let decoded = Message::decode(&message(options.clone(), b"payload").encode()).unwrap();
+
+        assert_eq!(decoded.kind, Type::Confirmable);
+        assert_eq!(decoded.code, GET);
+        assert_eq!(decoded.message_id, 0x1234);
+        assert_eq!(decoded.token, vec![0xab]);
+        assert_eq!(decoded.options, options);
+        assert_eq!(decoded.payload, b"payload");
+    }
+
+    #[test]
+    fn options_are_sent_in_order_of_their_numbers() {
+        let options = vec![(URI_QUERY, b"q".to_vec()), (URI_PATH, b"p".to_vec())];
+        let decoded = Message::decode(&message(options, b"").encode()).unwrap();
+
+        assert_eq!(
+            decoded.options,
+            vec![(URI_PATH, b"p".to_vec()), (URI_QUERY, b"q".to_vec())]
+        );
+    }
+
+    #[test]
+    fn truncated_messages_are_not_decoded() {
+        let encoded = message(vec![(URI_PATH, b"path".to_vec())], b"").encode();
+
+        assert!(Message::decode(&encoded[..3]).is_none());
+        assert!(Message::decode(&encoded[..encoded.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn overflowing_option_numbers_are_not_decoded() {
+        // A delta of 0xffff + 269 doesn't fit the option number
+        let data = [0x40, GET, 0, 1, 0xe0, 0xff, 0xff];
+        assert!(Message::decode(&data).is_none());
+
+        // Neither do two deltas that only overflow together
+        let data = [0x40, GET, 0, 1, 0xe0, 0xfe, 0x00, 0xe0, 0xfe, 0x00];
+        assert!(Message::decode(&data).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, error::Error, sync::Arc};
+use std::{collections::HashMap, error::Error, io, sync::Arc};
 
 use async_trait::async_trait;
 
@@ -26,11 +26,18 @@ impl CustomHttpClient {
 
 #[async_trait]
 impl HttpClient for CustomHttpClient {
-    async fn connect(&mut self, addr: Arc<String>) {
-        let connection = BufReader::new(TcpStream::connect(addr.to_string()).await.unwrap());
+    async fn connect(&mut self, addr: Arc<String>) -> Result<(), Box<dyn Error>> {
+        let connection = BufReader::new(TcpStream::connect(addr.to_string()).await?);
         self.connections.insert(addr.to_string(), connection);
+        Ok(())
     }
 
+    fn disconnect(&mut self, addr: &str) {
+        self.connections.remove(addr);
+    }
+
+    /// Connects to `addr` when not connected yet, or again after the
+    /// connection was lost.
     async fn request(
         &mut self,
         method: request::Method,
@@ -47,14 +54,38 @@ impl HttpClient for CustomHttpClient {
             todo!("Missing body");
         }
 
-        let stream = self.connections.get_mut(&addr.to_string()).unwrap();
+        if !self.connections.contains_key(addr.as_str()) {
+            self.connect(addr.clone()).await?;
+        }
 
-        stream.write_all(all_header.as_bytes()).await?;
+        let stream = self.connections.get_mut(addr.as_str()).unwrap();
+        let response = exchange(stream, &all_header).await;
+        if response.is_err() {
+            self.connections.remove(addr.as_str());
+        }
 
-        let len = stream.fill_buf().await.unwrap().len();
-        let mut buffer = vec![0u8; len];
-        stream.read_exact(&mut buffer).await?;
+        Ok(response?)
+    }
+}
 
-        Ok(buffer)
+async fn exchange(stream: &mut BufReader<TcpStream>, request: &str) -> io::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes()).await?;
+
+    let len = stream.fill_buf().await?.len();
+    if len == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
     }
+
+    let mut buffer = vec![0u8; len];
+    stream.read_exact(&mut buffer).await?;
+
+    Ok(buffer)
+}
+
+/// The status code of a response's status line, e.g. 200 for
+/// `HTTP/1.1 200 OK`.
+pub fn status(response: &[u8]) -> Option<u16> {
+    let line = response.split(|byte| *byte == b'\r').next()?;
+    let code = std::str::from_utf8(line).ok()?.split(' ').nth(1)?;
+    code.parse().ok()
 }
\ No newline at end of file
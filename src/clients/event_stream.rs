@@ -0,0 +1,62 @@
+use hyper::{body::HttpBody, Body};
+
+/// An event read off a `text/event-stream` body.
+#[derive(Debug)]
+pub struct Event {
+    /// Type of the event, `message` unless the server named it
+    pub event: String,
+}
+
+/// Splits a `text/event-stream` body into events. Blocks without data, like
+/// comments sent to keep the connection alive, aren't events.
+pub struct EventStream {
+    body: Body,
+    buffer: String,
+}
+
+impl EventStream {
+    pub fn new(body: Body) -> Self {
+        Self {
+            body,
+            buffer: String::new(),
+        }
+    }
+
+    /// The next event, `None` once the stream has ended.
+    pub async fn next(&mut self) -> Option<Result<Event, hyper::Error>> {
+        loop {
+            while let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                if let Some(event) = parse(&block) {
+                    return Some(Ok(event));
+                }
+            }
+
+            match self.body.data().await? {
+                Ok(chunk) => {
+                    let chunk = String::from_utf8_lossy(&chunk).replace("\r\n", "\n");
+                    self.buffer.push_str(&chunk);
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+fn parse(block: &str) -> Option<Event> {
+    let mut event = None;
+    let mut data = false;
+
+    for line in block.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        match field {
+            "event" => event = Some(value.trim_start().to_owned()),
+            "data" => data = true,
+            _ => {}
+        }
+    }
+
+    data.then(|| Event {
+        event: event.unwrap_or_else(|| "message".to_owned()),
+    })
+}
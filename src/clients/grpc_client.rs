@@ -0,0 +1,167 @@
+use std::{error::Error, str::FromStr, time::Duration};
+
+use hyper::{
+    body::{Buf, Bytes, HttpBody},
+    client::HttpConnector,
+    header::HeaderValue,
+    Body, Client, Request, StatusCode, Uri,
+};
+
+/// Length prefixed message framing, a compression flag and a 4 byte length.
+const FRAME_HEADER: usize = 5;
+
+pub const OK: u32 = 0;
+
+/// Status code of a call that ended without one
+const UNKNOWN: u32 = 2;
+
+const DEADLINE_EXCEEDED: u32 = 4;
+
+/// Calls over HTTP/2 without TLS, one connection per client.
+pub struct GrpcClient {
+    client: Client<HttpConnector, Body>,
+    addr: String,
+}
+
+/// Outcome of a call. Unary calls get a single message back.
+#[derive(Debug)]
+pub struct GrpcResponse {
+    pub status: u32,
+    pub messages: Vec<Bytes>,
+}
+
+impl GrpcClient {
+    pub fn new(addr: &str) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_nodelay(true);
+
+        Self {
+            client: Client::builder().http2_only(true).build(connector),
+            addr: addr.to_owned(),
+        }
+    }
+
+    /// Sends one request message to `path`, e.g. `/helloworld.Greeter/SayHello`,
+    /// and reads the response stream to its end. A call still running at its
+    /// `timeout` ends with DEADLINE_EXCEEDED, the messages read so far dropped.
+    pub async fn call(
+        &self,
+        path: &str,
+        metadata: &[(String, String)],
+        message: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<GrpcResponse, Box<dyn Error + Send + Sync>> {
+        let uri = Uri::from_str(&format!("http://{}{}", self.addr, path))?;
+
+        let mut body = Vec::with_capacity(FRAME_HEADER + message.len());
+        body.push(0);
+        body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        body.extend_from_slice(message);
+
+        let mut request = Request::post(uri)
+            .header("content-type", "application/grpc")
+            .header("te", "trailers");
+        for (key, value) in metadata {
+            request = request.header(key.as_str(), value.as_str());
+        }
+        if let Some(timeout) = timeout {
+            // The server gives up at the deadline as well
+            request = request.header("grpc-timeout", format!("{}m", timeout.as_millis()));
+        }
+
+        let call = self.exchange(request.body(Body::from(body))?);
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, call).await.unwrap_or(Ok(GrpcResponse {
+                status: DEADLINE_EXCEEDED,
+                messages: Vec::new(),
+            })),
+            None => call.await,
+        }
+    }
+
+    async fn exchange(
+        &self,
+        request: Request<Body>,
+    ) -> Result<GrpcResponse, Box<dyn Error + Send + Sync>> {
+        let response = self.client.request(request).await?;
+        let (parts, mut body) = response.into_parts();
+
+        if parts.status != StatusCode::OK {
+            let status = parts.headers.get("grpc-status").and_then(parse_status);
+            return Ok(GrpcResponse {
+                status: status.unwrap_or_else(|| http_status(parts.status)),
+                messages: Vec::new(),
+            });
+        }
+
+        let mut buffer = Vec::new();
+        let mut messages = Vec::new();
+
+        while let Some(data) = body.data().await {
+            buffer.extend_from_slice(data?.chunk());
+
+            while buffer.len() >= FRAME_HEADER {
+                let len = u32::from_be_bytes(buffer[1..FRAME_HEADER].try_into().unwrap()) as usize;
+                if buffer.len() < FRAME_HEADER + len {
+                    break;
+                }
+
+                let frame: Vec<u8> = buffer.drain(..FRAME_HEADER + len).collect();
+                messages.push(Bytes::copy_from_slice(&frame[FRAME_HEADER..]));
+            }
+        }
+
+        // A call failing right away answers with headers only
+        let trailers = body.trailers().await?;
+        let status = trailers
+            .as_ref()
+            .and_then(|trailers| trailers.get("grpc-status"))
+            .or_else(|| parts.headers.get("grpc-status"));
+
+        Ok(GrpcResponse {
+            status: status.and_then(parse_status).unwrap_or(UNKNOWN),
+            messages,
+        })
+    }
+}
+
+fn parse_status(value: &HeaderValue) -> Option<u32> {
+    value.to_str().ok()?.parse().ok()
+}
+
+/// Status code of a non 200 response without one, as the gRPC spec maps
+/// HTTP statuses.
+fn http_status(status: StatusCode) -> u32 {
+    match status.as_u16() {
+        400 => 13,
+        401 => 16,
+        403 => 7,
+        404 => 12,
+        429 | 502 | 503 | 504 => 14,
+        _ => UNKNOWN,
+    }
+}
+
+/// Name of a status code as the gRPC spec spells it.
+pub fn status_name(status: u32) -> &'static str {
+    match status {
+        0 => "OK",
+        1 => "CANCELLED",
+        2 => "UNKNOWN",
+        3 => "INVALID_ARGUMENT",
+        4 => "DEADLINE_EXCEEDED",
+        5 => "NOT_FOUND",
+        6 => "ALREADY_EXISTS",
+        7 => "PERMISSION_DENIED",
+        8 => "RESOURCE_EXHAUSTED",
+        9 => "FAILED_PRECONDITION",
+        10 => "ABORTED",
+        11 => "OUT_OF_RANGE",
+        12 => "UNIMPLEMENTED",
+        13 => "INTERNAL",
+        14 => "UNAVAILABLE",
+        15 => "DATA_LOSS",
+        16 => "UNAUTHENTICATED",
+        _ => "UNKNOWN",
+    }
+}
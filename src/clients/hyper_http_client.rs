@@ -1,42 +1,53 @@
-use std::{sync::Arc, error::Error, str::FromStr};
+use std::{error::Error, str::FromStr};
 
-use async_trait::async_trait;
-use hyper::{client::HttpConnector, Uri};
-
-use super::{client_trait::HttpClient, request::Method};
+use hyper::{body::Bytes, client::HttpConnector, Body, Request, Response, StatusCode, Uri};
 
+/// A pooling client for requests whose bodies the custom client can't read,
+/// like event streams.
 pub struct HyperHttpClient {
-    client: hyper::Client<HttpConnector>
+    client: hyper::Client<HttpConnector>,
 }
 
 impl HyperHttpClient {
     pub fn new() -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_nodelay(true);
+
         Self {
-            client: hyper::Client::new()
+            client: hyper::Client::builder().build(connector),
         }
     }
-}
 
-#[async_trait]
-impl HttpClient for HyperHttpClient {
-    async fn connect(&mut self, addr: Arc<String>) {
-        
+    /// Sends a GET and returns the response with its body unread, for bodies
+    /// that arrive over time.
+    pub async fn open(
+        &self,
+        addr: &str,
+        endpoint: &str,
+        accept: &str,
+    ) -> Result<Response<Body>, Box<dyn Error + Send + Sync>> {
+        let uri = Uri::from_str(&format!("http://{}{}", addr, endpoint))?;
+        let request = Request::get(uri).header("accept", accept).body(Body::empty())?;
+
+        Ok(self.client.request(request).await?)
     }
 
-    async fn request(
-        &mut self,
-        method: Method,
-        addr: Arc<String>,
-        endpoint: String,
-        headers: Arc<String>,
-        body: Option<Arc<String>>,
-    ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let addr = format!("http://{}{}", &addr.to_string(), &endpoint);
-        let uri = Uri::from_str(&addr)?;
-        let response = self.client.get(uri).await?;
-
-        let body = format!("{:?}", response).as_bytes().to_vec();
-
-        Ok(body)
+    /// Sends a POST with a JSON body and reads the whole response.
+    pub async fn post_json(
+        &self,
+        addr: &str,
+        endpoint: &str,
+        body: Vec<u8>,
+    ) -> Result<(StatusCode, Bytes), Box<dyn Error + Send + Sync>> {
+        let uri = Uri::from_str(&format!("http://{}{}", addr, endpoint))?;
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .header("accept", "application/json")
+            .body(Body::from(body))?;
+
+        let response = self.client.request(request).await?;
+        let status = response.status();
+
+        Ok((status, hyper::body::to_bytes(response.into_body()).await?))
     }
 }
@@ -1,4 +1,12 @@
 pub mod custom_http_client;
 pub mod client_trait;
+pub mod coap_client;
+pub mod event_stream;
+pub mod grpc_client;
+pub mod redis_client;
 pub mod request;
-pub mod hyper_http_client;
\ No newline at end of file
+pub mod socket_client;
+pub mod hyper_http_client;
+
+/// Largest datagram read, the most a UDP packet can carry
+pub const MAX_DATAGRAM: usize = 65_507;
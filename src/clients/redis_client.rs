@@ -0,0 +1,272 @@
+use std::io;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// A reply to a command, only as far as a load test cares about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Value,
+    /// A nil bulk string or array, e.g. GET of a missing key
+    Nil,
+    /// An error reply, its message starting with a kind like `ERR` or `WRONGTYPE`
+    Error(String),
+}
+
+/// One connection to a redis server speaking RESP, opened when first needed
+/// and again after it was lost.
+pub struct RedisClient {
+    addr: String,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    cursor: Cursor,
+}
+
+/// How far the reply at the start of the buffer is parsed, so a read only
+/// parses what it added.
+#[derive(Debug, Default)]
+struct Cursor {
+    position: usize,
+    /// Elements still to come of each aggregate the position lies in,
+    /// innermost last
+    pending: Vec<usize>,
+}
+
+/// A reply, or the header of an aggregate whose elements follow it.
+enum Element {
+    Reply(Reply),
+    Aggregate(usize),
+}
+
+impl RedisClient {
+    pub fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_owned(),
+            stream: None,
+            buffer: Vec::new(),
+            cursor: Cursor::default(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub async fn connect(&mut self) -> io::Result<()> {
+        let stream = TcpStream::connect(&self.addr).await?;
+        stream.set_nodelay(true)?;
+
+        self.stream = Some(stream);
+        self.buffer.clear();
+        self.cursor = Cursor::default();
+        Ok(())
+    }
+
+    /// Drops the connection, the next command opens a new one.
+    pub fn disconnect(&mut self) {
+        self.stream = None;
+        self.buffer.clear();
+        self.cursor = Cursor::default();
+    }
+
+    /// Writes all commands at once, each a list of arguments.
+    pub async fn send(&mut self, commands: &[Vec<Vec<u8>>]) -> io::Result<()> {
+        let mut data = Vec::new();
+        for command in commands {
+            encode(command, &mut data);
+        }
+
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_all(&data).await,
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    /// Reads the reply to the next command sent.
+    pub async fn receive(&mut self) -> io::Result<Reply> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(io::Error::from(io::ErrorKind::NotConnected))?;
+
+        loop {
+            if let Some(reply) = parse(&self.buffer, &mut self.cursor)? {
+                self.buffer.drain(..self.cursor.position);
+                self.cursor = Cursor::default();
+                return Ok(reply);
+            }
+
+            if stream.read_buf(&mut self.buffer).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// Appends a command as a RESP array of bulk strings.
+fn encode(command: &[Vec<u8>], data: &mut Vec<u8>) {
+    data.extend_from_slice(format!("*{}\r\n", command.len()).as_bytes());
+
+    for argument in command {
+        data.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+        data.extend_from_slice(argument);
+        data.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Parses on from the cursor, returning the reply once the data holds all of
+/// it. The cursor then points past it.
+fn parse(data: &[u8], cursor: &mut Cursor) -> io::Result<Option<Reply>> {
+    while let Some((element, end)) = parse_element(data, cursor.position)? {
+        cursor.position = end;
+
+        let reply = match element {
+            Element::Aggregate(elements) if elements > 0 => {
+                cursor.pending.push(elements);
+                continue;
+            }
+            Element::Aggregate(_) => Reply::Value,
+            Element::Reply(reply) => reply,
+        };
+
+        if cursor.pending.is_empty() {
+            return Ok(Some(reply));
+        }
+
+        // The element may be the last of the aggregates it completes
+        while let Some(remaining) = cursor.pending.last_mut() {
+            *remaining -= 1;
+            if *remaining > 0 {
+                break;
+            }
+            cursor.pending.pop();
+        }
+
+        if cursor.pending.is_empty() {
+            return Ok(Some(Reply::Value));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parses the element starting at `start`, returning it and where it ends, or
+/// `None` when the data doesn't hold all of it yet.
+fn parse_element(data: &[u8], start: usize) -> io::Result<Option<(Element, usize)>> {
+    let Some(line_end) = data[start..]
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|position| start + position)
+    else {
+        return Ok(None);
+    };
+
+    let line = String::from_utf8_lossy(&data[start + 1..line_end]);
+    let next = line_end + 2;
+
+    let length = || {
+        line.parse::<i64>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("bad length {line}")))
+    };
+    let reply = |reply| Ok(Some((Element::Reply(reply), next)));
+
+    match data.get(start) {
+        Some(b'+' | b':' | b',' | b'#' | b'(') => reply(Reply::Value),
+        Some(b'-') => reply(Reply::Error(line.to_string())),
+        Some(b'_') => reply(Reply::Nil),
+        Some(b'$' | b'=' | b'%' | b'*' | b'~' | b'>') if length()? < 0 => reply(Reply::Nil),
+        Some(b'$' | b'=') => {
+            let end = next + length()? as usize + 2;
+            Ok((data.len() >= end).then_some((Element::Reply(Reply::Value), end)))
+        }
+        Some(b'!') => {
+            // A blob error, its message may hold line breaks
+            let end = next + length()? as usize + 2;
+            Ok((data.len() >= end).then(|| {
+                let message = String::from_utf8_lossy(&data[next..end - 2]).into_owned();
+                (Element::Reply(Reply::Error(message)), end)
+            }))
+        }
+        Some(kind @ (b'*' | b'~' | b'>' | b'%')) => {
+            // Maps hold a key and a value per entry
+            let elements = length()? as usize * if *kind == b'%' { 2 } else { 1 };
+            Ok(Some((Element::Aggregate(elements), next)))
+        }
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown reply type {:?}", data[start] as char),
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first reply in `data` and where it ends.
+    fn parse_one(data: &[u8]) -> Option<(Reply, usize)> {
+        let mut cursor = Cursor::default();
+        let reply = parse(data, &mut cursor).unwrap()?;
+        Some((reply, cursor.position))
+    }
+
+    #[test]
+    fn simple_replies_end_with_their_line() {
+        assert_eq!(parse_one(b"+OK\r\n"), Some((Reply::Value, 5)));
+        assert_eq!(parse_one(b":42\r\n+next\r\n"), Some((Reply::Value, 5)));
+        assert_eq!(parse_one(b"_\r\n"), Some((Reply::Nil, 3)));
+        assert_eq!(
+            parse_one(b"-WRONGTYPE not a list\r\n"),
+            Some((Reply::Error("WRONGTYPE not a list".to_owned()), 23))
+        );
+    }
+
+    #[test]
+    fn bulk_strings_are_read_by_their_length() {
+        assert_eq!(parse_one(b"$5\r\nhe\r\no\r\n"), Some((Reply::Value, 11)));
+        assert_eq!(parse_one(b"$-1\r\n"), Some((Reply::Nil, 5)));
+        assert_eq!(parse_one(b"$5\r\nhel"), None);
+    }
+
+    #[test]
+    fn blob_errors_are_read_by_their_length() {
+        assert_eq!(
+            parse_one(b"!22\r\nSYNTAX invalid\r\nsyntax\r\n+next\r\n"),
+            Some((Reply::Error("SYNTAX invalid\r\nsyntax".to_owned()), 29))
+        );
+        assert_eq!(parse_one(b"!22\r\nSYNTAX"), None);
+    }
+
+    #[test]
+    fn aggregates_end_with_their_last_element() {
+        let data = b"*2\r\n*1\r\n:1\r\n$1\r\na\r\n+next\r\n";
+        assert_eq!(parse_one(data), Some((Reply::Value, 19)));
+
+        assert_eq!(parse_one(b"%1\r\n+key\r\n_\r\n"), Some((Reply::Value, 13)));
+        assert_eq!(parse_one(b"*0\r\n"), Some((Reply::Value, 4)));
+        assert_eq!(parse_one(b"*-1\r\n"), Some((Reply::Nil, 5)));
+        assert_eq!(parse_one(b"*2\r\n:1\r\n"), None);
+    }
+
+    #[test]
+    fn parsing_resumes_where_the_data_ran_out() {
+        let data = b"*2\r\n*2\r\n:1\r\n:2\r\n$3\r\nabc\r\n";
+        let mut cursor = Cursor::default();
+
+        for end in 1..data.len() {
+            assert_eq!(parse(&data[..end], &mut cursor).unwrap(), None);
+        }
+
+        assert_eq!(parse(data, &mut cursor).unwrap(), Some(Reply::Value));
+        assert_eq!(cursor.position, data.len());
+    }
+
+    #[test]
+    fn unknown_reply_types_are_invalid() {
+        let error = parse(b"?x\r\n", &mut Cursor::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+
+use regex::bytes::Regex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+use super::MAX_DATAGRAM;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// How the end of a response is found.
+#[derive(Debug, Clone)]
+pub enum Expect {
+    /// A number of bytes
+    Length(usize),
+    /// Everything up to and including a delimiter
+    Delimiter(Vec<u8>),
+    /// Everything up to the end of a match
+    Regex(Regex),
+}
+
+impl Expect {
+    /// Where a response found in `data` ends.
+    fn find(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Expect::Length(length) => (data.len() >= *length).then_some(*length),
+            Expect::Delimiter(delimiter) => data
+                .windows(delimiter.len())
+                .position(|window| window == delimiter.as_slice())
+                .map(|start| start + delimiter.len()),
+            Expect::Regex(regex) => regex.find(data).map(|found| found.end()),
+        }
+    }
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// One connection to `addr`, opened when first needed and again after it was
+/// lost. Bytes of a TCP stream read past a response are kept for the next.
+pub struct SocketClient {
+    transport: Transport,
+    addr: String,
+    socket: Option<Socket>,
+    buffer: Vec<u8>,
+}
+
+impl SocketClient {
+    pub fn new(transport: Transport, addr: &str) -> Self {
+        Self {
+            transport,
+            addr: addr.to_owned(),
+            socket: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    pub async fn connect(&mut self) -> io::Result<()> {
+        let socket = match self.transport {
+            Transport::Tcp => {
+                let stream = TcpStream::connect(&self.addr).await?;
+                stream.set_nodelay(true)?;
+                Socket::Tcp(stream)
+            }
+            Transport::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(&self.addr).await?;
+                Socket::Udp(socket)
+            }
+        };
+
+        self.socket = Some(socket);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Drops the connection, the next send or receive opens a new one.
+    pub fn disconnect(&mut self) {
+        self.socket = None;
+        self.buffer.clear();
+    }
+
+    pub async fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.socket.as_mut() {
+            Some(Socket::Tcp(stream)) => stream.write_all(data).await,
+            Some(Socket::Udp(socket)) => socket.send(data).await.map(|_| ()),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    /// Reads until a response is found and returns its size. Datagrams are
+    /// matched one at a time, those that don't match are skipped.
+    pub async fn receive(&mut self, expect: &Expect) -> io::Result<usize> {
+        match self.socket.as_mut() {
+            Some(Socket::Tcp(stream)) => loop {
+                if let Some(end) = expect.find(&self.buffer) {
+                    self.buffer.drain(..end);
+                    return Ok(end);
+                }
+
+                if stream.read_buf(&mut self.buffer).await? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            },
+            Some(Socket::Udp(socket)) => {
+                let mut datagram = vec![0; MAX_DATAGRAM];
+                loop {
+                    let len = socket.recv(&mut datagram).await?;
+                    if expect.find(&datagram[..len]).is_some() {
+                        return Ok(len);
+                    }
+                }
+            }
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_end_once_enough_bytes_arrived() {
+        let expect = Expect::Length(4);
+
+        assert_eq!(expect.find(b"abc"), None);
+        assert_eq!(expect.find(b"abcd"), Some(4));
+        assert_eq!(expect.find(b"abcdef"), Some(4));
+    }
+
+    #[test]
+    fn delimiters_end_after_their_first_occurrence() {
+        let expect = Expect::Delimiter(b"\r\n".to_vec());
+
+        assert_eq!(expect.find(b"ab\r"), None);
+        assert_eq!(expect.find(b"ab\r\ncd\r\n"), Some(4));
+        assert_eq!(expect.find(b""), None);
+    }
+
+    #[test]
+    fn regexes_end_with_their_match() {
+        let expect = Expect::Regex(Regex::new(r"OK \d+").unwrap());
+
+        assert_eq!(expect.find(b"xx OK 12 yy"), Some(8));
+        assert_eq!(expect.find(b"xx ERR 12"), None);
+    }
+}
@@ -0,0 +1,22 @@
+//! Load testing of http, mqtt, websocket, grpc, coap, redis and raw socket
+//! services. Scenarios come from YAML files or [`ScenarioBuilder`], and a run
+//! returns a [`Report`] of every group's steps and metrics.
+
+mod clients;
+pub mod mock;
+pub mod scenario;
+pub mod test_clients;
+mod utils;
+
+pub use scenario::{
+    builder::ScenarioBuilder,
+    error::ScenarioError,
+    report::{ArrivalReport, GroupReport, Report},
+    steps::{http::HttpStep, mqtt::MqttStep},
+    test_scenario::Scenario,
+};
+pub use test_clients::{
+    registry::{Protocol, Registry},
+    test_client::{Iteration, Metrics, Step, TestClient, TestClientData},
+};
+pub use utils::signal::shutdown_signal;
@@ -1,17 +1,47 @@
-mod clients;
-mod utils;
-mod scenario;
-mod test_clients;
+use std::error::Error;
 
+use loadtester_v2::{mock::MockServers, shutdown_signal, ScenarioBuilder};
 
-use std::{error::Error, sync::Arc};
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-use scenario::test_scenario::Scenario;
+    if args.first().map(String::as_str) == Some("serve-mock") {
+        return serve_mock(args.get(1).map_or("mock", String::as_str)).await;
+    }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let scenario = Arc::new(Scenario::new("testcase.http"));
+    // The run shuts down in order on the first Ctrl-C or SIGTERM, a second
+    // one exits right away
+    let exit = tokio::spawn(async {
+        shutdown_signal().await;
+        shutdown_signal().await;
+        std::process::exit(130);
+    });
+
+    let scenario = ScenarioBuilder::from_file("testcase.http")?
+        .progress(true)
+        .handle_signals(true)
+        .build()?;
     scenario.execute().await;
+    exit.abort();
+
+    Ok(())
+}
+
+/// Runs the mock servers of `./scenarios/<name>.yml` until Ctrl-C.
+async fn serve_mock(name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let file = std::fs::File::open(format!("./scenarios/{name}.yml"))?;
+    let mock_map: serde_yaml::Value = serde_yaml::from_reader(file)?;
+
+    let servers = MockServers::start(&mock_map).await?;
+    if let Some(http) = &servers.http {
+        println!("HTTP mock listening on {}", http.local_addr());
+    }
+    if let Some(mqtt) = &servers.mqtt {
+        println!("MQTT broker listening on {}", mqtt.local_addr());
+    }
+
+    tokio::signal::ctrl_c().await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use serde_yaml::Value;
+
+use crate::utils;
+
+/// How long a mock waits before it answers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delay {
+    Fixed(Duration),
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+    /// Cut off at zero
+    Normal {
+        mean: Duration,
+        std_dev: Duration,
+    },
+    Exponential {
+        mean: Duration,
+    },
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Self::Fixed(Duration::ZERO)
+    }
+}
+
+impl Delay {
+    /// Reads a `delay` key, which is either a time or a mapping with a
+    /// `distribution` and its parameters. A missing key is no delay.
+    pub fn from_value(delay: &Value) -> Self {
+        let time = |key: &str| {
+            let time = delay[key]
+                .as_str()
+                .unwrap_or_else(|| panic!("Missing delay {key} in {delay:?}"));
+            Duration::from_millis(utils::time::string_to_millis_u128(time) as u64)
+        };
+
+        match delay {
+            Value::Null => Self::default(),
+            Value::Mapping(_) => match delay["distribution"].as_str().unwrap_or("fixed") {
+                "fixed" => Self::Fixed(time("time")),
+                "uniform" => Self::Uniform {
+                    min: time("min"),
+                    max: time("max"),
+                },
+                "normal" => Self::Normal {
+                    mean: time("mean"),
+                    std_dev: time("std-dev"),
+                },
+                "exponential" => Self::Exponential { mean: time("mean") },
+                distribution => panic!("Unknown delay distribution: {distribution}"),
+            },
+            _ => {
+                let time = delay.as_str().unwrap();
+                Self::Fixed(Duration::from_millis(
+                    utils::time::string_to_millis_u128(time) as u64,
+                ))
+            }
+        }
+    }
+
+    /// Draws a delay.
+    pub fn sample(&self) -> Duration {
+        match *self {
+            Self::Fixed(time) => time,
+            Self::Uniform { min, max } => min + max.saturating_sub(min).mul_f64(rand::random()),
+            Self::Normal { mean, std_dev } => {
+                // Box-Muller, 1 - u keeps the logarithm finite
+                let u: f64 = 1.0 - rand::random::<f64>();
+                let v: f64 = rand::random();
+                let z = (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+
+                Duration::from_secs_f64((mean.as_secs_f64() + z * std_dev.as_secs_f64()).max(0.0))
+            }
+            Self::Exponential { mean } => {
+                let u: f64 = 1.0 - rand::random::<f64>();
+                mean.mul_f64(-u.ln())
+            }
+        }
+    }
+}
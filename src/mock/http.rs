@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error::Error,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use serde_yaml::Value;
+use tokio::task::JoinHandle;
+
+use crate::utils;
+
+use super::delay::Delay;
+
+/// How a mock answers requests to one path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub delay: Delay,
+    /// Share of requests, from 0 to 1, answered with `error_status`
+    pub error_rate: f64,
+    pub error_status: u16,
+    pub status: u16,
+    /// `None` echoes the request body, or its method and path when it has none
+    pub body: Option<String>,
+    /// Answers with an event stream instead of a body
+    pub events: Option<Events>,
+}
+
+/// A `text/event-stream` of `count` events, the first sent right after the
+/// delay and the others `interval` apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Events {
+    pub count: u64,
+    pub interval: Duration,
+    /// Types the events take in turn, none sends them untyped
+    pub types: Vec<String>,
+}
+
+impl Events {
+    /// Reads an `events` key. Its `event` is a type or a list of them.
+    pub fn from_value(events: &Value) -> Self {
+        let interval = events["interval"].as_str().unwrap_or("0s");
+        let types = match &events["event"] {
+            Value::String(event) => vec![event.clone()],
+            Value::Sequence(events) => events
+                .iter()
+                .map(|event| event.as_str().unwrap().to_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            count: events["count"].as_u64().unwrap_or(1),
+            interval: Duration::from_millis(utils::time::string_to_millis_u128(interval) as u64),
+            types,
+        }
+    }
+
+    fn body(&self) -> Body {
+        let (mut sender, body) = Body::channel();
+        let events = self.clone();
+
+        tokio::spawn(async move {
+            for n in 0..events.count {
+                if n > 0 {
+                    tokio::time::sleep(events.interval).await;
+                }
+
+                let event = match events.types.len() {
+                    0 => format!("data: {n}\n\n"),
+                    types => format!("event: {}\ndata: {n}\n\n", events.types[n as usize % types]),
+                };
+
+                // The client hung up
+                if sender.send_data(event.into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        body
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self {
+            delay: Delay::default(),
+            error_rate: 0.0,
+            error_status: 500,
+            status: 200,
+            body: None,
+            events: None,
+        }
+    }
+}
+
+impl Endpoint {
+    pub fn from_value(endpoint: &Value) -> Self {
+        let default = Self::default();
+
+        Self {
+            delay: Delay::from_value(&endpoint["delay"]),
+            error_rate: endpoint["error-rate"]
+                .as_f64()
+                .unwrap_or(default.error_rate),
+            error_status: endpoint["error-status"]
+                .as_u64()
+                .map_or(default.error_status, |status| status as u16),
+            status: endpoint["status"]
+                .as_u64()
+                .map_or(default.status, |status| status as u16),
+            body: endpoint["body"].as_str().map(str::to_owned),
+            events: endpoint.get("events").map(Events::from_value),
+        }
+    }
+}
+
+/// Endpoints of an HTTP mock by path. Other paths are answered by `fallback`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpMockConfig {
+    pub endpoints: HashMap<String, Endpoint>,
+    pub fallback: Endpoint,
+}
+
+impl HttpMockConfig {
+    /// Reads an `http` section, whose own endpoint keys are the fallback's.
+    pub fn from_value(http: &Value) -> Self {
+        let endpoints = http["endpoints"]
+            .as_mapping()
+            .map(|endpoints| {
+                endpoints
+                    .iter()
+                    .map(|(path, endpoint)| {
+                        (
+                            path.as_str().unwrap().to_owned(),
+                            Endpoint::from_value(endpoint),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            endpoints,
+            fallback: Endpoint::from_value(http),
+        }
+    }
+}
+
+/// An HTTP/1.1 echo server running in-process until dropped.
+pub struct HttpMock {
+    addr: SocketAddr,
+    requests: Arc<Mutex<HashMap<String, usize>>>,
+    task: JoinHandle<()>,
+}
+
+impl HttpMock {
+    /// Starts serving on `addr`, port 0 picks a free one.
+    pub async fn start(
+        addr: SocketAddr,
+        config: HttpMockConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let config = Arc::new(config);
+        let requests: Arc<Mutex<HashMap<String, usize>>> = Arc::default();
+
+        let counts = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let config = config.clone();
+            let counts = counts.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    respond(request, config.clone(), counts.clone())
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?
+            .tcp_nodelay(true)
+            .serve(make_service);
+        let addr = server.local_addr();
+
+        let task = tokio::spawn(async move {
+            let _ = server.await;
+        });
+
+        Ok(Self {
+            addr,
+            requests,
+            task,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of requests received for `path`, answered or not.
+    pub fn requests(&self, path: &str) -> usize {
+        self.requests
+            .lock()
+            .unwrap()
+            .get(path)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for HttpMock {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn respond(
+    request: Request<Body>,
+    config: Arc<HttpMockConfig>,
+    requests: Arc<Mutex<HashMap<String, usize>>>,
+) -> Result<Response<Body>, Infallible> {
+    let path = request.uri().path().to_owned();
+    let endpoint = config.endpoints.get(&path).unwrap_or(&config.fallback);
+
+    *requests.lock().unwrap().entry(path).or_default() += 1;
+
+    tokio::time::sleep(endpoint.delay.sample()).await;
+
+    let status = |status: u16| StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+
+    if rand::random::<f64>() < endpoint.error_rate {
+        let mut response = Response::new(Body::from("mock error"));
+        *response.status_mut() = status(endpoint.error_status);
+        return Ok(response);
+    }
+
+    if let Some(events) = &endpoint.events {
+        let mut response = Response::new(events.body());
+        *response.status_mut() = status(endpoint.status);
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+        return Ok(response);
+    }
+
+    let content_type = request.headers().get(CONTENT_TYPE).cloned();
+    let body = match &endpoint.body {
+        Some(body) => Body::from(body.clone()),
+        None => {
+            let line = format!("{} {}", request.method(), request.uri());
+            let body = hyper::body::to_bytes(request.into_body())
+                .await
+                .unwrap_or_default();
+
+            if body.is_empty() {
+                Body::from(line)
+            } else {
+                Body::from(body)
+            }
+        }
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = status(endpoint.status);
+    if let (None, Some(content_type)) = (&endpoint.body, content_type) {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+
+    Ok(response)
+}
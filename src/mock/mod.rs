@@ -0,0 +1,49 @@
+pub mod delay;
+pub mod http;
+pub mod mqtt;
+
+use std::{
+    error::Error,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+use serde_yaml::Value;
+
+use self::{
+    http::{HttpMock, HttpMockConfig},
+    mqtt::{MqttBroker, MqttMockConfig},
+};
+
+/// The mock targets of a `mock` file, stopped when dropped.
+pub struct MockServers {
+    pub http: Option<HttpMock>,
+    pub mqtt: Option<MqttBroker>,
+}
+
+impl MockServers {
+    /// Starts the servers whose section, `http` or `mqtt`, is under the `mock`
+    /// key, on localhost at the section's `port`, by default 9090 and 1883.
+    pub async fn start(mock_map: &Value) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mock = &mock_map["mock"];
+        let addr = |section: &Value, port: u16| {
+            let port = section["port"].as_u64().map_or(port, |port| port as u16);
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+        };
+
+        let http = match mock.get("http") {
+            Some(http) => {
+                Some(HttpMock::start(addr(http, 9090), HttpMockConfig::from_value(http)).await?)
+            }
+            None => None,
+        };
+
+        let mqtt = match mock.get("mqtt") {
+            Some(mqtt) => {
+                Some(MqttBroker::start(addr(mqtt, 1883), MqttMockConfig::from_value(mqtt)).await?)
+            }
+            None => None,
+        };
+
+        Ok(Self { http, mqtt })
+    }
+}
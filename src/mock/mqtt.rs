@@ -0,0 +1,343 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::BytesMut;
+use rumqttc::{
+    mqttbytes::{self, v4},
+    ConnAck, ConnectReturnCode, LastWill, Packet, PingResp, PubAck, PubComp, PubRec, PubRel,
+    Publish, QoS, SubAck, SubscribeReasonCode, UnsubAck,
+};
+use serde_yaml::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::delay::Delay;
+
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MqttMockConfig {
+    /// Time a publish takes to reach the subscribers
+    pub delay: Delay,
+}
+
+impl MqttMockConfig {
+    pub fn from_value(mqtt: &Value) -> Self {
+        Self {
+            delay: Delay::from_value(&mqtt["delay"]),
+        }
+    }
+}
+
+/// A minimal MQTT 3.1.1 broker running in-process until dropped. It routes
+/// publishes, also to wildcard and `$share/<group>/` subscriptions, and sends
+/// wills, but keeps no sessions or retained messages.
+pub struct MqttBroker {
+    addr: SocketAddr,
+    publishes: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+    /// Dropped with the broker, which ends the connections
+    _closed: watch::Sender<()>,
+}
+
+struct Session {
+    outgoing: mpsc::UnboundedSender<BytesMut>,
+    subscriptions: Vec<(String, QoS)>,
+    last_pkid: u16,
+}
+
+#[derive(Default)]
+struct Sessions {
+    sessions: HashMap<u64, Session>,
+    next_id: u64,
+    /// Member of each shared subscription whose turn it was last
+    turns: HashMap<String, usize>,
+}
+
+/// State every connection shares.
+#[derive(Clone)]
+struct Broker {
+    sessions: Arc<Mutex<Sessions>>,
+    publishes: Arc<AtomicUsize>,
+    delay: Delay,
+}
+
+impl MqttBroker {
+    /// Starts listening on `addr`, port 0 picks a free one.
+    pub async fn start(addr: SocketAddr, config: MqttMockConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+
+        let broker = Broker {
+            sessions: Arc::default(),
+            publishes: Arc::default(),
+            delay: config.delay,
+        };
+        let publishes = broker.publishes.clone();
+        let (closed, closing) = watch::channel(());
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let broker = broker.clone();
+                let mut closing = closing.clone();
+
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = broker.serve(stream) => {}
+                        _ = closing.changed() => {}
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            addr,
+            publishes,
+            task,
+            _closed: closed,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of publishes received from clients.
+    pub fn publishes(&self) -> usize {
+        self.publishes.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MqttBroker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Broker {
+    /// Handles one client connection. A will is sent unless the client
+    /// disconnected with DISCONNECT.
+    async fn serve(&self, stream: TcpStream) {
+        let _ = stream.set_nodelay(true);
+        let (mut reader, mut writer) = stream.into_split();
+        let (outgoing, mut packets) = mpsc::unbounded_channel::<BytesMut>();
+
+        let writing = tokio::spawn(async move {
+            while let Some(packet) = packets.recv().await {
+                if writer.write_all(&packet).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut buffer = BytesMut::new();
+        let mut session = None;
+        let mut will: Option<LastWill> = None;
+        let mut graceful = false;
+
+        loop {
+            let packet = match v4::read(&mut buffer, MAX_PACKET_SIZE) {
+                Ok(packet) => packet,
+                Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                    match reader.read_buf(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => continue,
+                    }
+                }
+                Err(_) => break,
+            };
+
+            let reply = match (packet, session) {
+                (Packet::Connect(connect), None) => {
+                    will = connect.last_will;
+                    session = Some(self.open(outgoing.clone()));
+                    encode(|buffer| ConnAck::new(ConnectReturnCode::Success, false).write(buffer))
+                }
+                (_, None) | (Packet::Connect(_), Some(_)) => break,
+                (Packet::Publish(publish), Some(_)) => {
+                    let reply = match publish.qos {
+                        QoS::AtMostOnce => None,
+                        QoS::AtLeastOnce => {
+                            encode(|buffer| PubAck::new(publish.pkid).write(buffer))
+                        }
+                        QoS::ExactlyOnce => {
+                            encode(|buffer| PubRec::new(publish.pkid).write(buffer))
+                        }
+                    };
+
+                    self.publishes.fetch_add(1, Ordering::SeqCst);
+                    self.publish(publish);
+                    reply
+                }
+                (Packet::PubRec(pubrec), Some(_)) => {
+                    encode(|buffer| PubRel::new(pubrec.pkid).write(buffer))
+                }
+                (Packet::PubRel(pubrel), Some(_)) => {
+                    encode(|buffer| PubComp::new(pubrel.pkid).write(buffer))
+                }
+                (Packet::Subscribe(subscribe), Some(id)) => {
+                    let codes = subscribe
+                        .filters
+                        .iter()
+                        .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                        .collect();
+
+                    self.update(id, |subscriptions| {
+                        for filter in subscribe.filters {
+                            subscriptions.retain(|(path, _)| *path != filter.path);
+                            subscriptions.push((filter.path, filter.qos));
+                        }
+                    });
+                    encode(|buffer| SubAck::new(subscribe.pkid, codes).write(buffer))
+                }
+                (Packet::Unsubscribe(unsubscribe), Some(id)) => {
+                    self.update(id, |subscriptions| {
+                        subscriptions.retain(|(path, _)| !unsubscribe.topics.contains(path))
+                    });
+                    encode(|buffer| UnsubAck::new(unsubscribe.pkid).write(buffer))
+                }
+                (Packet::PingReq, Some(_)) => encode(|buffer| PingResp.write(buffer)),
+                (Packet::Disconnect, Some(_)) => {
+                    graceful = true;
+                    break;
+                }
+                (_, Some(_)) => None,
+            };
+
+            if let Some(reply) = reply {
+                let _ = outgoing.send(reply);
+            }
+        }
+
+        if let Some(id) = session {
+            self.sessions.lock().unwrap().sessions.remove(&id);
+        }
+
+        if let (false, Some(will)) = (graceful, will) {
+            let mut publish = Publish::new(will.topic, will.qos, will.message.to_vec());
+            publish.retain = will.retain;
+            self.publish(publish);
+        }
+
+        // Lets the writer send what is queued, e.g. the reply to a DISCONNECT
+        drop(outgoing);
+        let _ = writing.await;
+    }
+
+    fn open(&self, outgoing: mpsc::UnboundedSender<BytesMut>) -> u64 {
+        let mut sessions = self.sessions.lock().unwrap();
+        let id = sessions.next_id;
+
+        sessions.next_id += 1;
+        sessions.sessions.insert(
+            id,
+            Session {
+                outgoing,
+                subscriptions: Vec::new(),
+                last_pkid: 0,
+            },
+        );
+
+        id
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut Vec<(String, QoS)>)) {
+        if let Some(session) = self.sessions.lock().unwrap().sessions.get_mut(&id) {
+            change(&mut session.subscriptions);
+        }
+    }
+
+    /// Passes a publish on to its subscribers after the configured delay.
+    fn publish(&self, publish: Publish) {
+        let delay = self.delay.sample();
+
+        if delay.is_zero() {
+            self.route(&publish);
+        } else {
+            let broker = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                broker.route(&publish);
+            });
+        }
+    }
+
+    /// Sends a copy to every session with a matching subscription, and one
+    /// to a member of each matching shared subscription in turn.
+    fn route(&self, publish: &Publish) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Sessions {
+            sessions, turns, ..
+        } = &mut *sessions;
+
+        let mut receivers: Vec<(u64, QoS)> = Vec::new();
+        let mut groups: BTreeMap<String, Vec<(u64, QoS)>> = BTreeMap::new();
+
+        for (id, session) in sessions.iter() {
+            let mut granted: Option<QoS> = None;
+
+            for (path, qos) in &session.subscriptions {
+                match path
+                    .strip_prefix("$share/")
+                    .and_then(|shared| shared.split_once('/'))
+                {
+                    Some((_, filter)) if rumqttc::matches(&publish.topic, filter) => {
+                        groups.entry(path.clone()).or_default().push((*id, *qos));
+                    }
+                    // Overlapping subscriptions get one copy at the highest QoS
+                    None if rumqttc::matches(&publish.topic, path)
+                        && granted.is_none_or(|granted| granted < *qos) =>
+                    {
+                        granted = Some(*qos);
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(qos) = granted {
+                receivers.push((*id, qos));
+            }
+        }
+
+        for (group, mut members) in groups {
+            members.sort_by_key(|(id, _)| *id);
+            let turn = turns.entry(group).or_default();
+            *turn = (*turn + 1) % members.len();
+            receivers.push(members[*turn]);
+        }
+
+        for (id, qos) in receivers {
+            let session = sessions.get_mut(&id).unwrap();
+            let qos = if qos < publish.qos { qos } else { publish.qos };
+
+            let mut delivery = Publish::new(publish.topic.clone(), qos, publish.payload.to_vec());
+            if qos != QoS::AtMostOnce {
+                session.last_pkid = session.last_pkid % u16::MAX + 1;
+                delivery.pkid = session.last_pkid;
+            }
+
+            if let Some(packet) = encode(|buffer| delivery.write(buffer)) {
+                let _ = session.outgoing.send(packet);
+            }
+        }
+    }
+}
+
+fn encode(
+    write: impl FnOnce(&mut BytesMut) -> Result<usize, mqttbytes::Error>,
+) -> Option<BytesMut> {
+    let mut buffer = BytesMut::new();
+    write(&mut buffer).ok()?;
+    Some(buffer)
+}
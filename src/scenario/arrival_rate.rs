@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use serde_yaml::Value;
+
+use crate::utils;
+
+use super::stages::{Stage, Stages};
+
+/// Settings of the open-model executor from the scenario's `arrival-rate`
+/// key. Iterations start at the given rate no matter how long earlier ones
+/// take, on whichever client of the pool is free.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrivalRate {
+    /// Iterations per second over time
+    rates: Stages,
+    pre_allocated: usize,
+    max_clients: usize,
+}
+
+impl ArrivalRate {
+    /// Reads either a constant `rate` for the scenario's `duration`, or a list
+    /// of `stages` with a `rate` and `duration` each. Rates are per
+    /// `time-unit`, which defaults to a second. `None` for a scenario without
+    /// an arrival rate.
+    pub fn from_scenario(scenario: &Value) -> Result<Option<Self>, String> {
+        let Some(arrival_rate) = scenario.get("arrival-rate") else {
+            return Ok(None);
+        };
+
+        let time_unit = arrival_rate["time-unit"].as_str().unwrap_or("1s");
+        let time_unit = utils::time::string_to_millis_u128(time_unit) as f64 / 1000.0;
+
+        let rates = match Stages::from_value(&arrival_rate["stages"], "rate")? {
+            Some(stages) => stages,
+            None => {
+                let (Some(rate), Some(duration)) =
+                    (arrival_rate["rate"].as_f64(), scenario["duration"].as_str())
+                else {
+                    return Err("arrival-rate needs stages, or a rate and a duration".to_owned());
+                };
+                let duration = utils::time::string_to_millis_u128(duration) as u64;
+
+                Stages::new(vec![
+                    Stage::new(rate, Duration::ZERO),
+                    Stage::new(rate, Duration::from_millis(duration)),
+                ])
+            }
+        };
+
+        let max_clients = arrival_rate["max-clients"]
+            .as_u64()
+            .ok_or("arrival-rate needs max-clients")? as usize;
+        let pre_allocated = arrival_rate["pre-allocated"]
+            .as_u64()
+            .map_or(max_clients, |pre_allocated| pre_allocated as usize)
+            .min(max_clients);
+
+        Ok(Some(Self {
+            rates: rates.scaled(1.0 / time_unit),
+            pre_allocated,
+            max_clients,
+        }))
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.rates.total_duration()
+    }
+
+    /// Iterations per second `elapsed` into the run.
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        self.rates.target_at(elapsed)
+    }
+
+    pub fn pre_allocated(&self) -> usize {
+        self.pre_allocated
+    }
+
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+}
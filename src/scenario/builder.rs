@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use serde_yaml::{Mapping, Value};
+
+use crate::test_clients::registry::Registry;
+
+use super::{
+    error::ScenarioError,
+    test_scenario::{self, Scenario},
+};
+
+/// Defines a scenario in code instead of a scenario file. The keys are the
+/// file's, so anything the builder has no method for can be `set` directly.
+pub struct ScenarioBuilder {
+    scenario: Mapping,
+    registry: Option<Registry>,
+    progress: bool,
+    signals: bool,
+}
+
+impl ScenarioBuilder {
+    pub fn new() -> Self {
+        Self {
+            scenario: Mapping::new(),
+            registry: None,
+            progress: false,
+            signals: false,
+        }
+    }
+
+    /// Starts from the scenario file `./scenarios/<name>.yml`, whose keys
+    /// the builder's override.
+    pub fn from_file(scenario_name: &str) -> Result<Self, ScenarioError> {
+        let scenario_map = test_scenario::load_scenario(scenario_name)?;
+
+        let scenario = match &scenario_map["scenario"] {
+            Value::Mapping(scenario) => scenario.clone(),
+            _ => return Err(ScenarioError::Missing("scenario")),
+        };
+
+        Ok(Self {
+            scenario,
+            ..Self::new()
+        })
+    }
+
+    /// Sets a key of the `scenario` section.
+    pub fn set(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.scenario.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn protocol(self, protocol: &str) -> Self {
+        self.set("protocol", protocol)
+    }
+
+    pub fn host(self, host: &str) -> Self {
+        self.set("host", host)
+    }
+
+    pub fn port(self, port: u16) -> Self {
+        self.set("port", port)
+    }
+
+    pub fn clients(self, clients: usize) -> Self {
+        self.set("clients", clients)
+    }
+
+    /// Share of the clients left over by the other groups, for a group.
+    pub fn weight(self, weight: f64) -> Self {
+        self.set("weight", weight)
+    }
+
+    pub fn duration(self, duration: Duration) -> Self {
+        self.set("duration", time(duration))
+    }
+
+    pub fn ramp_up(self, ramp_up: Duration) -> Self {
+        self.set("ramp-up", time(ramp_up))
+    }
+
+    pub fn ramp_down(self, ramp_down: Duration) -> Self {
+        self.set("ramp-down", time(ramp_down))
+    }
+
+    pub fn grace_period(self, grace_period: Duration) -> Self {
+        self.set("grace-period", time(grace_period))
+    }
+
+    /// Exports the report to `path`, as JSON when it ends in `.json` and as
+    /// YAML otherwise.
+    pub fn report_file(self, path: &str) -> Self {
+        self.set("report-file", path)
+    }
+
+    /// Iterations of each client.
+    pub fn iterations(self, iterations: usize) -> Self {
+        self.set_in("testloop", "iterations", iterations)
+    }
+
+    /// Iterations of all clients together.
+    pub fn total_iterations(self, iterations: usize) -> Self {
+        self.set_in("testloop", "total-iterations", iterations)
+    }
+
+    pub fn interval(self, interval: Duration) -> Self {
+        self.set_in("testloop", "interval", time(interval))
+    }
+
+    pub fn pacing(self, pacing: Duration) -> Self {
+        self.set_in("testloop", "pacing", time(pacing))
+    }
+
+    /// Adds a step to the test loop, a typed one like [`HttpStep`] or the
+    /// YAML of any protocol's, e.g. `endpoint: /` for http.
+    ///
+    /// [`HttpStep`]: super::steps::http::HttpStep
+    pub fn step(self, step: impl Into<Value>) -> Self {
+        self.push_step("testloop", step.into())
+    }
+
+    pub fn pretest_step(self, step: impl Into<Value>) -> Self {
+        self.push_step("pretest", step.into())
+    }
+
+    pub fn posttest_step(self, step: impl Into<Value>) -> Self {
+        self.push_step("posttest", step.into())
+    }
+
+    /// Adds a group, whose keys override the scenario's for its clients.
+    pub fn group(mut self, name: &str, group: ScenarioBuilder) -> Self {
+        self.section("groups")
+            .insert(name.into(), Value::Mapping(group.scenario));
+        self
+    }
+
+    /// Protocols the scenario can use, the built-in ones by default.
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Draws a progress bar while the test loop runs, clearing the
+    /// terminal. Off by default.
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Stops the run in order on Ctrl-C or SIGTERM, still running posttest,
+    /// teardown and the report. Off by default, which leaves the signals to
+    /// the program.
+    pub fn handle_signals(mut self, signals: bool) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// The scenario, checked like a scenario file.
+    pub fn build(self) -> Result<Scenario, ScenarioError> {
+        let mut scenario_map = Mapping::new();
+        scenario_map.insert("scenario".into(), Value::Mapping(self.scenario));
+
+        let registry = self.registry.unwrap_or_default();
+        let mut scenario = Scenario::from_map(Value::Mapping(scenario_map), &registry)?;
+        scenario.progress = self.progress;
+        scenario.signals = self.signals;
+        Ok(scenario)
+    }
+
+    fn set_in(mut self, section: &str, key: &str, value: impl Into<Value>) -> Self {
+        self.section(section).insert(key.into(), value.into());
+        self
+    }
+
+    fn push_step(mut self, section: &str, step: Value) -> Self {
+        let mut wrapped = Mapping::new();
+        wrapped.insert("step".into(), step);
+
+        let steps = self
+            .section(section)
+            .entry("steps".into())
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+        steps
+            .as_sequence_mut()
+            .unwrap()
+            .push(Value::Mapping(wrapped));
+        self
+    }
+
+    /// The mapping under `key`, created when missing.
+    fn section(&mut self, key: &str) -> &mut Mapping {
+        self.scenario
+            .entry(key.into())
+            .or_insert_with(|| Value::Mapping(Mapping::new()))
+            .as_mapping_mut()
+            .unwrap()
+    }
+}
+
+impl Default for ScenarioBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A duration as a scenario file writes it.
+pub(super) fn time(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
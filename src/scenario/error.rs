@@ -0,0 +1,27 @@
+use std::{error::Error, fmt};
+
+/// Why a scenario couldn't be loaded or built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScenarioError {
+    /// The scenario file couldn't be read or isn't YAML
+    File { path: String, error: String },
+    /// A key every run needs, e.g. `protocol`, isn't set
+    Missing(&'static str),
+    UnknownProtocol(String),
+    /// A key holds something the scenario can't run, or the protocol
+    /// rejected its section or one of its steps
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::File { path, error } => write!(f, "Couldn't load {path}: {error}"),
+            ScenarioError::Missing(key) => write!(f, "No {key} specified"),
+            ScenarioError::UnknownProtocol(protocol) => write!(f, "Unknown protocol: {protocol}"),
+            ScenarioError::Invalid(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for ScenarioError {}
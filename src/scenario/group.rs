@@ -0,0 +1,275 @@
+use serde_yaml::Value;
+
+use super::{error::ScenarioError, ramp::Ramp};
+
+/// A named set of clients with its own flow, from the scenario's `groups`
+/// mapping. Its keys, e.g. `ramp-up` or `protocol`, override the scenario's
+/// for its clients. Its `testloop` overrides the scenario's key by key, so a
+/// group can change the interval and keep the steps. A scenario without
+/// groups is one unnamed group.
+#[derive(Debug, Clone)]
+pub struct Group {
+    name: Option<String>,
+    /// Fixed client count, taking precedence over the weight
+    clients: Option<usize>,
+    weight: f64,
+    ramp_up: Ramp,
+    scenario_map: Value,
+}
+
+impl Group {
+    pub fn from_scenario(scenario_map: &Value) -> Result<Vec<Self>, ScenarioError> {
+        let groups = match scenario_map["scenario"]["groups"].as_mapping() {
+            Some(groups) => groups,
+            None => {
+                let group = Self::new(None, &Value::Null, scenario_map.clone())?;
+                return Ok(vec![group]);
+            }
+        };
+
+        groups
+            .iter()
+            .map(|(name, group)| {
+                let (Some(name), Some(keys)) = (name.as_str(), group.as_mapping()) else {
+                    return Err(ScenarioError::Invalid(format!(
+                        "Group {name:?} isn't a mapping under a name"
+                    )));
+                };
+
+                let mut group_map = scenario_map.clone();
+                let scenario = group_map["scenario"].as_mapping_mut().unwrap();
+                scenario.remove(&Value::from("groups"));
+
+                for (key, value) in keys {
+                    match (key.as_str(), scenario.get_mut(key), value) {
+                        (Some("clients" | "weight"), _, _) => {}
+                        (
+                            Some("testloop"),
+                            Some(Value::Mapping(testloop)),
+                            Value::Mapping(keys),
+                        ) => {
+                            for (key, value) in keys {
+                                testloop.insert(key.clone(), value.clone());
+                            }
+                        }
+                        _ => {
+                            scenario.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+
+                Self::new(Some(name.to_owned()), group, group_map)
+            })
+            .collect()
+    }
+
+    /// A group reading its own keys, e.g. `weight`, from `group`.
+    fn new(
+        name: Option<String>,
+        group: &Value,
+        scenario_map: Value,
+    ) -> Result<Self, ScenarioError> {
+        let ramp_up = Ramp::from_scenario(&scenario_map["scenario"], "ramp-up")
+            .map_err(ScenarioError::Invalid)?;
+
+        Ok(Self {
+            name,
+            clients: group["clients"].as_u64().map(|clients| clients as usize),
+            weight: group["weight"].as_f64().unwrap_or(1.0),
+            ramp_up,
+            scenario_map,
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Fixed client count, `None` for a share of the scenario's clients.
+    pub fn clients(&self) -> Option<usize> {
+        self.clients
+    }
+
+    pub fn protocol(&self) -> &str {
+        self.scenario_map["scenario"]["protocol"]
+            .as_str()
+            .expect("No protocol specified")
+    }
+
+    pub fn ramp_up(&self) -> &Ramp {
+        &self.ramp_up
+    }
+
+    /// The scenario as the group's clients see it.
+    pub fn scenario_map(&self) -> &Value {
+        &self.scenario_map
+    }
+}
+
+/// Client count of each group. Groups with a fixed count get it, the others
+/// share what is left of `total` by weight.
+pub fn split_clients(groups: &[Group], total: Option<usize>) -> Vec<usize> {
+    let fixed: usize = groups.iter().filter_map(|group| group.clients).sum();
+    let weighted: Vec<&Group> = groups.iter().filter(|group| group.clients.is_none()).collect();
+
+    let shared = if weighted.is_empty() {
+        0
+    } else {
+        total.expect("No clients specified").saturating_sub(fixed)
+    };
+
+    let total_weight: f64 = weighted.iter().map(|group| group.weight).sum();
+    let shares: Vec<f64> = groups
+        .iter()
+        .map(|group| match group.clients {
+            Some(_) => 0.0,
+            None => shared as f64 * group.weight / total_weight,
+        })
+        .collect();
+
+    let mut counts: Vec<usize> = groups
+        .iter()
+        .zip(shares.iter())
+        .map(|(group, share)| group.clients.unwrap_or(share.floor() as usize))
+        .collect();
+
+    // Clients lost to rounding go to the groups with the largest remainders
+    let mut remainders: Vec<usize> = (0..groups.len())
+        .filter(|i| groups[*i].clients.is_none())
+        .collect();
+    remainders.sort_by(|a, b| (shares[*b] % 1.0).total_cmp(&(shares[*a] % 1.0)));
+
+    let assigned: usize = counts.iter().sum::<usize>() - fixed;
+    for i in remainders.into_iter().take(shared - assigned) {
+        counts[i] += 1;
+    }
+
+    counts
+}
+
+/// Order in which the groups' clients are created, so every prefix of the
+/// client pool holds the groups in proportion to their counts.
+pub fn interleave(counts: &[usize]) -> Vec<usize> {
+    let total: usize = counts.iter().sum();
+    let mut current = vec![0isize; counts.len()];
+    let mut order = Vec::with_capacity(total);
+
+    for _ in 0..total {
+        for (credit, count) in current.iter_mut().zip(counts) {
+            *credit += *count as isize;
+        }
+
+        let (group, _) = current
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, credit)| (**credit, std::cmp::Reverse(*i)))
+            .unwrap();
+
+        current[group] -= total as isize;
+        order.push(group);
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(yaml: &str) -> Vec<Group> {
+        Group::from_scenario(&serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn fixed_counts_are_kept_and_the_rest_shared_by_weight() {
+        let groups = groups(
+            r#"
+scenario:
+  groups:
+    fixed:
+      clients: 5
+    heavy:
+      weight: 3
+    light:
+      weight: 1
+"#,
+        );
+
+        assert_eq!(split_clients(&groups, Some(13)), vec![5, 6, 2]);
+    }
+
+    #[test]
+    fn clients_lost_to_rounding_are_handed_out() {
+        let groups = groups(
+            r#"
+scenario:
+  groups:
+    first: {}
+    second: {}
+    third: {}
+"#,
+        );
+
+        assert_eq!(split_clients(&groups, Some(10)), vec![4, 3, 3]);
+    }
+
+    #[test]
+    fn fixed_counts_need_no_total() {
+        let groups = groups(
+            r#"
+scenario:
+  groups:
+    first:
+      clients: 2
+    second:
+      clients: 1
+"#,
+        );
+
+        assert_eq!(split_clients(&groups, None), vec![2, 1]);
+    }
+
+    #[test]
+    fn group_testloops_override_the_scenario_key_by_key() {
+        let groups = groups(
+            r#"
+scenario:
+  protocol: http
+  testloop:
+    interval: 10ms
+    steps:
+      - step:
+          endpoint: /
+  groups:
+    slow:
+      testloop:
+        interval: 1s
+    other:
+      protocol: mqtt
+      testloop:
+        steps: []
+"#,
+        );
+
+        let testloop = |group: &Group| group.scenario_map()["scenario"]["testloop"].clone();
+
+        assert_eq!(testloop(&groups[0])["interval"].as_str(), Some("1s"));
+        assert_eq!(testloop(&groups[0])["steps"].as_sequence().unwrap().len(), 1);
+        assert_eq!(testloop(&groups[1])["interval"].as_str(), Some("10ms"));
+        assert!(testloop(&groups[1])["steps"].as_sequence().unwrap().is_empty());
+        assert_eq!(groups[1].protocol(), "mqtt");
+    }
+
+    #[test]
+    fn interleaving_keeps_groups_in_proportion() {
+        assert_eq!(interleave(&[2, 1]), vec![0, 1, 0]);
+        assert_eq!(interleave(&[3, 1]), vec![0, 0, 1, 0]);
+        assert_eq!(interleave(&[1, 1, 1]), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn interleaving_skips_empty_groups() {
+        assert_eq!(interleave(&[0, 2]), vec![1, 1]);
+        assert!(interleave(&[]).is_empty());
+    }
+}
@@ -1 +1,9 @@
+pub mod arrival_rate;
+pub mod builder;
+pub mod error;
+pub mod group;
+pub mod ramp;
+pub mod report;
+pub mod stages;
+pub mod steps;
 pub mod test_scenario;
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use serde_yaml::Value;
+
+use crate::utils;
+
+/// Growth rate of the exponential curve, the last client starts e^5 times
+/// faster than the first.
+const EXPONENTIAL_RATE: f64 = 5.0;
+
+/// How client starts (or stops) are spread over the ramp time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RampCurve {
+    Linear,
+    Exponential,
+    /// Clients start in this many equally sized batches
+    Step(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ramp {
+    time: Duration,
+    curve: RampCurve,
+}
+
+impl Ramp {
+    pub fn new(time: Duration, curve: RampCurve) -> Self {
+        Self { time, curve }
+    }
+
+    /// Reads a ramp key, which is either a time or a mapping with `time`,
+    /// `curve` and, for the step curve, `steps`. A missing key is no ramp.
+    pub fn from_scenario(scenario: &Value, key: &str) -> Result<Self, String> {
+        let ramp = &scenario[key];
+
+        let (time, curve) = match ramp {
+            Value::Mapping(_) => (ramp["time"].as_str().unwrap_or("0ms"), &ramp["curve"]),
+            _ => (ramp.as_str().unwrap_or("0ms"), &Value::Null),
+        };
+
+        let curve = match curve.as_str().unwrap_or("linear") {
+            "linear" => RampCurve::Linear,
+            "exponential" => RampCurve::Exponential,
+            "step" => RampCurve::Step(ramp["steps"].as_u64().unwrap_or(1).max(1) as usize),
+            curve => return Err(format!("Unknown ramp curve: {curve}")),
+        };
+
+        let time = utils::time::string_to_millis_u128(time) as u64;
+
+        Ok(Self::new(Duration::from_millis(time), curve))
+    }
+
+    /// Offset from the start of the ramp at which client `index` of `total`
+    /// starts. The first client always starts right away.
+    pub fn offset(&self, index: usize, total: usize) -> Duration {
+        if total == 0 {
+            return Duration::ZERO;
+        }
+
+        let share = index as f64 / total as f64;
+
+        let fraction = match self.curve {
+            RampCurve::Linear => share,
+            RampCurve::Exponential => {
+                (1.0 + share * (EXPONENTIAL_RATE.exp() - 1.0)).ln() / EXPONENTIAL_RATE
+            }
+            RampCurve::Step(steps) => (share * steps as f64).floor() / steps as f64,
+        };
+
+        self.time.mul_f64(fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(curve: RampCurve, total: usize) -> Vec<Duration> {
+        let ramp = Ramp::new(Duration::from_secs(10), curve);
+        (0..total).map(|index| ramp.offset(index, total)).collect()
+    }
+
+    #[test]
+    fn linear_ramps_start_clients_evenly() {
+        let expected: Vec<Duration> = [0, 2500, 5000, 7500]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+
+        assert_eq!(offsets(RampCurve::Linear, 4), expected);
+    }
+
+    #[test]
+    fn exponential_ramps_start_clients_ever_faster() {
+        let offsets = offsets(RampCurve::Exponential, 4);
+        let gaps: Vec<Duration> = offsets.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+        assert_eq!(offsets[0], Duration::ZERO);
+        assert!(gaps.windows(2).all(|pair| pair[1] < pair[0]), "{gaps:?}");
+        assert!(offsets[3] < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn step_ramps_start_clients_in_batches() {
+        let expected: Vec<Duration> = [0, 0, 5, 5].into_iter().map(Duration::from_secs).collect();
+
+        assert_eq!(offsets(RampCurve::Step(2), 4), expected);
+    }
+
+    #[test]
+    fn no_clients_need_no_offset() {
+        let ramp = Ramp::new(Duration::from_secs(10), RampCurve::Linear);
+
+        assert_eq!(ramp.offset(0, 0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{error::Error, fmt, path::Path, time::Duration};
+
+use hdrhistogram::Histogram;
+use serde_json::{json, Value};
+
+use crate::test_clients::test_client::Step;
+
+/// Results of a run, what `Scenario::execute` prints.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Time the test loop ran for, the basis of the rates
+    pub elapsed: Duration,
+    /// Whether the run was stopped by Ctrl-C or SIGTERM
+    pub aborted: bool,
+    /// Only for runs with an arrival rate
+    pub arrivals: Option<ArrivalReport>,
+    pub groups: Vec<GroupReport>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrivalReport {
+    /// Iterations that found no free client
+    pub dropped: usize,
+    pub clients_used: usize,
+    pub clients: usize,
+}
+
+/// Steps and metrics of one group's clients, added up.
+#[derive(Debug, Clone)]
+pub struct GroupReport {
+    /// `None` for a scenario without groups
+    pub name: Option<String>,
+    pub protocol: String,
+    pub clients: usize,
+    /// In the order of the test loop
+    pub steps: Vec<Step>,
+    /// In the order the protocol declares them
+    pub metrics: Vec<(String, Step)>,
+}
+
+impl Report {
+    /// The group called `name`, or the only one of a scenario without groups
+    /// for `None`.
+    pub fn group(&self, name: Option<&str>) -> Option<&GroupReport> {
+        self.groups
+            .iter()
+            .find(|group| group.name.as_deref() == name)
+    }
+
+    /// The report as JSON, latencies in milliseconds.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "elapsed-ms": self.elapsed.as_millis() as u64,
+            "aborted": self.aborted,
+            "arrivals": self.arrivals.map(|arrivals| json!({
+                "dropped": arrivals.dropped,
+                "clients-used": arrivals.clients_used,
+                "clients": arrivals.clients,
+            })),
+            "groups": self
+                .groups
+                .iter()
+                .map(|group| self.group_json(group))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Writes the report to `path`, as JSON when it ends in `.json` and as
+    /// YAML otherwise.
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let report = self.to_json();
+
+        let data = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => serde_json::to_string_pretty(&report)?,
+            _ => serde_yaml::to_string(&report)?,
+        };
+
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn group_json(&self, group: &GroupReport) -> Value {
+        let steps: Vec<Value> = group
+            .steps
+            .iter()
+            .map(|step| {
+                json!({
+                    "step": serde_json::to_value(step.step()).unwrap_or_default(),
+                    "count": step.count(),
+                    "rate": self.rate(step.count()),
+                    "interrupted": step.interrupted(),
+                    "raw": latencies(step.raw()),
+                    "corrected": latencies(step.corrected()),
+                })
+            })
+            .collect();
+
+        let metrics: Vec<Value> = group
+            .metrics
+            .iter()
+            .map(|(name, metric)| {
+                json!({
+                    "name": name,
+                    "count": metric.count(),
+                    "rate": self.rate(metric.count()),
+                    // Counters took no time, see the Display impl
+                    "time-ms": (!metric.raw().is_empty() || metric.time() > 0)
+                        .then(|| metric.time() as u64),
+                    "raw": (!metric.raw().is_empty()).then(|| latencies(metric.raw())),
+                })
+            })
+            .collect();
+
+        json!({
+            "name": group.name,
+            "protocol": group.protocol,
+            "clients": group.clients,
+            "steps": steps,
+            "metrics": metrics,
+        })
+    }
+
+    /// Per second rate of `count` over the run, 0 for a run aborted before
+    /// its test loop started.
+    fn rate(&self, count: usize) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+
+        count as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl GroupReport {
+    pub fn metric(&self, name: &str) -> Option<&Step> {
+        self.metrics
+            .iter()
+            .find(|(metric, _)| metric == name)
+            .map(|(_, metric)| metric)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(arrivals) = &self.arrivals {
+            writeln!(f, "Dropped iterations: {}", arrivals.dropped)?;
+            writeln!(
+                f,
+                "Clients used: {} of {}",
+                arrivals.clients_used, arrivals.clients
+            )?;
+        }
+
+        if self.aborted {
+            writeln!(
+                f,
+                "Run aborted after {:.2} s, results cover the elapsed portion only",
+                self.elapsed.as_secs_f64()
+            )?;
+        }
+
+        for group in &self.groups {
+            if let Some(name) = &group.name {
+                writeln!(
+                    f,
+                    "Group {} ({}, {} clients):",
+                    name, group.protocol, group.clients
+                )?;
+            }
+
+            for (i, step) in group.steps.iter().enumerate() {
+                writeln!(
+                    f,
+                    "Step #{}: {:.2} ms, {} req/sec, {} total, {} interrupted",
+                    i,
+                    step.raw().mean() / 1000.0,
+                    self.rate(step.count()) as u32,
+                    step.count(),
+                    step.interrupted()
+                )?;
+                writeln!(f, "  raw:       {}", percentiles(step.raw()))?;
+                writeln!(f, "  corrected: {}", percentiles(step.corrected()))?;
+            }
+
+            for (name, metric) in &group.metrics {
+                // Counters, e.g. of bytes or errors, took no time to average
+                if metric.raw().is_empty() && metric.time() == 0 {
+                    writeln!(
+                        f,
+                        "{}: {} total, {:.2}/sec",
+                        name,
+                        metric.count(),
+                        self.rate(metric.count())
+                    )?;
+                    continue;
+                }
+
+                // Metrics recorded with their latency average those samples,
+                // which needn't be all that is counted
+                let avg = if metric.raw().is_empty() {
+                    metric.time() as f64 / metric.count() as f64
+                } else {
+                    metric.raw().mean() / 1000.0
+                };
+
+                writeln!(
+                    f,
+                    "{}: {:.2} ms avg, {} total, {:.2}/sec",
+                    name,
+                    avg,
+                    metric.count(),
+                    self.rate(metric.count())
+                )?;
+
+                // Only metrics recorded with their latency have percentiles
+                if !metric.raw().is_empty() {
+                    writeln!(f, "  raw:       {}", percentiles(metric.raw()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Mean and percentiles in milliseconds of a histogram in microseconds.
+fn latencies(histogram: &Histogram<u64>) -> Value {
+    let millis = |micros: u64| micros as f64 / 1000.0;
+
+    json!({
+        "mean": histogram.mean() / 1000.0,
+        "p50": millis(histogram.value_at_quantile(0.5)),
+        "p90": millis(histogram.value_at_quantile(0.9)),
+        "p99": millis(histogram.value_at_quantile(0.99)),
+        "p99.9": millis(histogram.value_at_quantile(0.999)),
+        "max": millis(histogram.max()),
+    })
+}
+
+/// Latency percentiles in milliseconds of a histogram in microseconds.
+fn percentiles(histogram: &Histogram<u64>) -> String {
+    let millis = |micros: u64| micros as f64 / 1000.0;
+
+    format!(
+        "p50 {:.2} ms, p90 {:.2} ms, p99 {:.2} ms, p99.9 {:.2} ms, max {:.2} ms",
+        millis(histogram.value_at_quantile(0.5)),
+        millis(histogram.value_at_quantile(0.9)),
+        millis(histogram.value_at_quantile(0.99)),
+        millis(histogram.value_at_quantile(0.999)),
+        millis(histogram.max()),
+    )
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use serde_yaml::Value;
+
+use crate::utils;
+
+/// One entry of a `stages` list. The target, e.g. the client count, moves
+/// linearly from the previous stage's target to this one over its duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stage {
+    target: f64,
+    duration: Duration,
+}
+
+impl Stage {
+    pub fn new(target: f64, duration: Duration) -> Self {
+        Self { target, duration }
+    }
+}
+
+/// A load profile of consecutive stages, starting from zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stages {
+    stages: Vec<Stage>,
+}
+
+impl Stages {
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Self { stages }
+    }
+
+    /// Reads a `stages` list, each entry having `duration` and the target
+    /// under `target_key`. `None` when there is no list.
+    pub fn from_value(stages: &Value, target_key: &str) -> Result<Option<Self>, String> {
+        let Some(stages) = stages.as_sequence() else {
+            return Ok(None);
+        };
+
+        let stages = stages
+            .iter()
+            .map(|stage| match (stage["duration"].as_str(), stage[target_key].as_f64()) {
+                (Some(duration), Some(target)) => Ok(Stage::new(
+                    target,
+                    Duration::from_millis(utils::time::string_to_millis_u128(duration) as u64),
+                )),
+                _ => Err(format!("Stage without duration or {target_key}: {stage:?}")),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(Self::new(stages)))
+    }
+
+    /// The same profile with every target multiplied by `factor`.
+    pub fn scaled(&self, factor: f64) -> Self {
+        let stages = self
+            .stages
+            .iter()
+            .map(|stage| Stage::new(stage.target * factor, stage.duration))
+            .collect();
+
+        Self::new(stages)
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.stages.iter().map(|stage| stage.duration).sum()
+    }
+
+    /// The highest target of any stage, e.g. the size of the client pool.
+    pub fn max_target(&self) -> f64 {
+        self.stages
+            .iter()
+            .map(|stage| stage.target)
+            .fold(0.0, f64::max)
+    }
+
+    /// The target `elapsed` into the profile.
+    pub fn target_at(&self, elapsed: Duration) -> f64 {
+        let mut previous = 0.0;
+        let mut stage_start = Duration::ZERO;
+
+        for stage in self.stages.iter() {
+            let stage_end = stage_start + stage.duration;
+
+            if elapsed < stage_end {
+                let progress = (elapsed - stage_start).as_secs_f64() / stage.duration.as_secs_f64();
+
+                return previous + (stage.target - previous) * progress;
+            }
+
+            previous = stage.target;
+            stage_start = stage_end;
+        }
+
+        previous
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stages() -> Stages {
+        let scenario: Value = serde_yaml::from_str(
+            r#"
+stages:
+  - { clients: 10, duration: 10s }
+  - { clients: 10, duration: 5s }
+  - { clients: 0, duration: 10s }
+"#,
+        )
+        .unwrap();
+
+        Stages::from_value(&scenario["stages"], "clients")
+            .unwrap()
+            .unwrap()
+    }
+
+    fn target_at(seconds: u64) -> f64 {
+        stages().target_at(Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn targets_move_linearly_from_zero() {
+        assert_eq!(target_at(0), 0.0);
+        assert_eq!(target_at(5), 5.0);
+    }
+
+    #[test]
+    fn targets_move_from_the_previous_stage() {
+        assert_eq!(target_at(10), 10.0);
+        assert_eq!(target_at(12), 10.0);
+        assert_eq!(target_at(20), 5.0);
+    }
+
+    #[test]
+    fn the_last_target_holds_after_the_profile() {
+        assert_eq!(target_at(25), 0.0);
+        assert_eq!(target_at(60), 0.0);
+        assert_eq!(Stages::new(Vec::new()).target_at(Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn the_pool_fits_the_largest_target() {
+        assert_eq!(stages().max_target(), 10.0);
+        assert_eq!(stages().total_duration(), Duration::from_secs(25));
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use serde_yaml::Value;
+
+use super::StepMap;
+
+/// A step of the `http` protocol. `{id}` and extracted variables in
+/// endpoints are filled in when the step runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HttpStep {
+    /// A GET request to the endpoint
+    Get(String),
+    Sse(Sse),
+    LongPoll(LongPoll),
+    Graphql(Graphql),
+}
+
+/// Reads an event stream until it has delivered `events` events, `duration`
+/// has passed or the server ends it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sse {
+    pub endpoint: String,
+    pub events: Option<u64>,
+    pub duration: Option<Duration>,
+    /// Only events of this type are counted
+    pub event: Option<String>,
+}
+
+/// Requests the endpoint again as soon as it answers, `polls` times.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LongPoll {
+    pub endpoint: String,
+    pub polls: Option<u64>,
+    /// Time a poll is held before it is given up and issued again
+    pub timeout: Option<Duration>,
+}
+
+/// Posts a GraphQL operation to the endpoint.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Graphql {
+    pub endpoint: String,
+    /// Sent as written, variables aren't filled into it
+    pub query: String,
+    pub operation: Option<String>,
+    pub variables: Vec<(String, String)>,
+    /// Variables set from the response data, by their dot path
+    pub extract: Vec<(String, String)>,
+}
+
+impl HttpStep {
+    pub fn get(endpoint: &str) -> Self {
+        Self::Get(endpoint.to_owned())
+    }
+}
+
+impl From<HttpStep> for Value {
+    fn from(step: HttpStep) -> Self {
+        match step {
+            HttpStep::Get(endpoint) => StepMap::default().set("endpoint", endpoint).into(),
+            HttpStep::Sse(sse) => {
+                let options = StepMap::default()
+                    .set_some("events", sse.events)
+                    .set_time("duration", sse.duration)
+                    .set_some("event", sse.event);
+
+                StepMap::default()
+                    .set("endpoint", sse.endpoint)
+                    .set("sse", options)
+                    .into()
+            }
+            HttpStep::LongPoll(long_poll) => {
+                let options = StepMap::default()
+                    .set_some("polls", long_poll.polls)
+                    .set_time("timeout", long_poll.timeout);
+
+                StepMap::default()
+                    .set("endpoint", long_poll.endpoint)
+                    .set("long-poll", options)
+                    .into()
+            }
+            HttpStep::Graphql(graphql) => {
+                let options = StepMap::default()
+                    .set("query", graphql.query)
+                    .set_some("operation", graphql.operation)
+                    .set_some("variables", mapping(graphql.variables))
+                    .set_some("extract", mapping(graphql.extract));
+
+                StepMap::default()
+                    .set("endpoint", graphql.endpoint)
+                    .set("graphql", options)
+                    .into()
+            }
+        }
+    }
+}
+
+impl From<Sse> for HttpStep {
+    fn from(sse: Sse) -> Self {
+        Self::Sse(sse)
+    }
+}
+
+impl From<LongPoll> for HttpStep {
+    fn from(long_poll: LongPoll) -> Self {
+        Self::LongPoll(long_poll)
+    }
+}
+
+impl From<Graphql> for HttpStep {
+    fn from(graphql: Graphql) -> Self {
+        Self::Graphql(graphql)
+    }
+}
+
+/// The pairs as a mapping, `None` when there are none.
+fn mapping(pairs: Vec<(String, String)>) -> Option<Value> {
+    (!pairs.is_empty()).then(|| {
+        Value::Mapping(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    })
+}
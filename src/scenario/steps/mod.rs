@@ -0,0 +1,40 @@
+//! Steps of the built-in protocols for [`ScenarioBuilder`], each lowering to
+//! the `step` a scenario file would hold.
+//!
+//! [`ScenarioBuilder`]: super::builder::ScenarioBuilder
+
+use std::time::Duration;
+
+use serde_yaml::{Mapping, Value};
+
+pub mod http;
+pub mod mqtt;
+
+/// A step's mapping, built key by key. Optional keys that aren't set are
+/// left out, so the protocol's defaults apply.
+#[derive(Default)]
+struct StepMap(Mapping);
+
+impl StepMap {
+    fn set(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.0.insert(key.into(), value.into());
+        self
+    }
+
+    fn set_some(self, key: &str, value: Option<impl Into<Value>>) -> Self {
+        match value {
+            Some(value) => self.set(key, value),
+            None => self,
+        }
+    }
+
+    fn set_time(self, key: &str, time: Option<Duration>) -> Self {
+        self.set_some(key, time.map(super::builder::time))
+    }
+}
+
+impl From<StepMap> for Value {
+    fn from(step: StepMap) -> Self {
+        Value::Mapping(step.0)
+    }
+}
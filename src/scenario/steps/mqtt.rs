@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use serde_yaml::Value;
+
+use super::StepMap;
+
+/// A step of the `mqtt` protocol. `{id}` in topics and filters is filled in
+/// when the step runs. Steps without a timeout wait the protocol's default.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MqttStep {
+    Publish(Publish),
+    /// Waits for a message on a topic matching the filter
+    Await { filter: String, timeout: Option<Duration> },
+    Subscribe {
+        filter: String,
+        qos: Option<u8>,
+        timeout: Option<Duration>,
+    },
+    Unsubscribe { filter: String, timeout: Option<Duration> },
+}
+
+/// Publishes a message and waits until the broker acknowledged it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    /// Without one the send time is published
+    pub payload: Option<String>,
+    pub qos: Option<u8>,
+    pub timeout: Option<Duration>,
+}
+
+impl MqttStep {
+    pub fn publish(topic: &str) -> Self {
+        Self::Publish(Publish {
+            topic: topic.to_owned(),
+            ..Publish::default()
+        })
+    }
+
+    pub fn await_message(filter: &str) -> Self {
+        Self::Await {
+            filter: filter.to_owned(),
+            timeout: None,
+        }
+    }
+
+    pub fn subscribe(filter: &str) -> Self {
+        Self::Subscribe {
+            filter: filter.to_owned(),
+            qos: None,
+            timeout: None,
+        }
+    }
+
+    pub fn unsubscribe(filter: &str) -> Self {
+        Self::Unsubscribe {
+            filter: filter.to_owned(),
+            timeout: None,
+        }
+    }
+}
+
+impl From<Publish> for MqttStep {
+    fn from(publish: Publish) -> Self {
+        Self::Publish(publish)
+    }
+}
+
+impl From<MqttStep> for Value {
+    fn from(step: MqttStep) -> Self {
+        let step = match step {
+            MqttStep::Publish(publish) => StepMap::default()
+                .set("publish", publish.topic)
+                .set_some("payload", publish.payload)
+                .set_some("qos", publish.qos)
+                .set_time("timeout", publish.timeout),
+            MqttStep::Await { filter, timeout } => StepMap::default()
+                .set("await", filter)
+                .set_time("timeout", timeout),
+            MqttStep::Subscribe {
+                filter,
+                qos,
+                timeout,
+            } => StepMap::default()
+                .set("subscribe", filter)
+                .set_some("qos", qos)
+                .set_time("timeout", timeout),
+            MqttStep::Unsubscribe { filter, timeout } => StepMap::default()
+                .set("unsubscribe", filter)
+                .set_time("timeout", timeout),
+        };
+
+        step.into()
+    }
+}
@@ -1,171 +1,639 @@
 use serde_yaml::Value;
 use std::{
-    sync::Arc,
-    thread,
+    sync::{
+        atomic::{AtomicIsize, AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
-use tokio::sync::broadcast::Sender;
+use tokio::{sync::watch, task::JoinHandle};
 
 use crate::{
     test_clients::{
-        test_client::TestClient, test_http_client::TestHttpClient, test_mqtt_client::TestMqttClient,
+        registry::{Protocol, Registry},
+        test_client::{Arrivals, Metrics, Step, TestClient},
     },
-    utils,
+    utils::{self, time::Timer},
+};
+
+use super::{
+    arrival_rate::ArrivalRate,
+    builder::ScenarioBuilder,
+    error::ScenarioError,
+    group::{self, Group},
+    ramp::Ramp,
+    report::{ArrivalReport, GroupReport, Report},
+    stages::Stages,
 };
 
+/// How often the client count is adjusted to the stages.
+const STAGE_TICK: Duration = Duration::from_millis(10);
+/// How often the arrival-rate executor hands out the iterations that are due.
+const ARRIVAL_TICK: Duration = Duration::from_millis(1);
+/// Time an aborted run gives the test loops past the grace period and their
+/// protocol's shutdown to end.
+const STOP_MARGIN: Duration = Duration::from_millis(100);
+
+/// The test loop task of each client, once it was started.
+type Tasks = Vec<Option<JoinHandle<()>>>;
+
+/// A client together with the sender that stops its test loop, so clients
+/// can be retired one by one during ramp-down.
+struct VirtualClient {
+    client: Arc<dyn TestClient>,
+    stop: watch::Sender<bool>,
+    /// Index of the client's group
+    group: usize,
+}
+
+impl VirtualClient {
+    fn start(&self) -> JoinHandle<()> {
+        self.stop.send_replace(false);
+        self.client.test_loop()
+    }
+
+    fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+}
+
 pub struct Scenario {
-    ramp_up_millis: u128,
-    duration_millis: u128,
-    clients: Vec<Arc<dyn TestClient>>,
-    scenario_map: Value,
-    tx: Sender<bool>,
+    groups: Vec<Group>,
+    /// Protocol of each group
+    protocols: Vec<Arc<dyn Protocol>>,
+    ramp_down: Ramp,
+    /// `None` when the run ends once the clients have run their iterations
+    duration_millis: Option<u128>,
+    total_iterations: Option<usize>,
+    stages: Option<Stages>,
+    arrival_rate: Option<ArrivalRate>,
+    clients: Vec<VirtualClient>,
+    /// File the report is exported to, aborted runs' too
+    report_file: Option<String>,
+    /// Draw a progress bar while the test loop runs
+    pub(super) progress: bool,
+    /// Stop the run in order on Ctrl-C or SIGTERM
+    pub(super) signals: bool,
 }
 
 impl Scenario {
-    pub fn new(scenario_name: &'static str) -> Self {
-        let file_path = format!("./scenarios/{scenario_name}.yml");
+    pub fn new(scenario_name: &str) -> Result<Self, ScenarioError> {
+        Self::with_registry(scenario_name, &Registry::default())
+    }
 
-        let scenario_map = utils::file::load_yaml(&file_path).unwrap();
-        let scenario = &scenario_map["scenario"];
-        let protocol = scenario["protocol"].as_str().unwrap();
+    /// Loads a scenario whose groups can use any protocol of `registry`.
+    pub fn with_registry(
+        scenario_name: &str,
+        registry: &Registry,
+    ) -> Result<Self, ScenarioError> {
+        let scenario_map = load_scenario(scenario_name)?;
 
-        let clients_size = scenario["clients"].as_u64().unwrap() as usize;
-        let host = scenario["host"].as_str().unwrap().to_owned();
-        let port = scenario["port"].as_u64().unwrap() as u16;
-        let duration = scenario["duration"].as_str().unwrap();
-        let ramp_up = scenario["ramp-up"].as_str().unwrap();
+        Self::from_map(scenario_map, registry)
+    }
+
+    /// A scenario from a map with a `scenario` key, like a scenario file's.
+    pub fn from_value(scenario_map: Value) -> Result<Self, ScenarioError> {
+        Self::from_map(scenario_map, &Registry::default())
+    }
 
-        let ramp_up_millis = utils::time::string_to_millis_u128(ramp_up);
-        let duration_millis = utils::time::string_to_millis_u128(duration);
+    /// Defines a scenario in code, see [`ScenarioBuilder`].
+    pub fn builder() -> ScenarioBuilder {
+        ScenarioBuilder::new()
+    }
 
-        let (tx, _) = tokio::sync::broadcast::channel(1);
+    pub(super) fn from_map(
+        scenario_map: Value,
+        registry: &Registry,
+    ) -> Result<Self, ScenarioError> {
+        let scenario = &scenario_map["scenario"];
+        if scenario.as_mapping().is_none() {
+            return Err(ScenarioError::Missing("scenario"));
+        }
 
-        let clients = match protocol {
-            "http" => create_http_clients(clients_size, &host, port, &scenario_map, &tx),
-            "mqtt" => create_mqtt_clients(clients_size, &host, port, &scenario_map, &tx),
-            _ => panic!("No protocol specified"),
+        // Stages replace clients, ramps and duration with a load profile, an
+        // arrival rate replaces them with a pool of clients to run iterations on
+        let stages =
+            Stages::from_value(&scenario["stages"], "clients").map_err(ScenarioError::Invalid)?;
+        let arrival_rate = ArrivalRate::from_scenario(scenario).map_err(ScenarioError::Invalid)?;
+        let (clients_size, duration_millis) = match (&arrival_rate, &stages) {
+            (Some(arrival_rate), _) => (
+                Some(arrival_rate.max_clients()),
+                Some(arrival_rate.duration().as_millis()),
+            ),
+            (None, Some(stages)) => (
+                Some(stages.max_target().ceil() as usize),
+                Some(stages.total_duration().as_millis()),
+            ),
+            (None, None) => (
+                scenario["clients"].as_u64().map(|clients| clients as usize),
+                scenario["duration"]
+                    .as_str()
+                    .map(utils::time::string_to_millis_u128),
+            ),
         };
 
-        Self {
-            ramp_up_millis,
+        let groups = Group::from_scenario(&scenario_map)?;
+        if groups.iter().any(|group| group.scenario_map()["scenario"]["protocol"].is_null()) {
+            return Err(ScenarioError::Missing("protocol"));
+        }
+
+        let total_iterations = utils::file::get_total_iterations(&scenario_map);
+        let iteration_limited = groups
+            .iter()
+            .all(|group| utils::file::get_iterations(group.scenario_map()).is_some());
+        if duration_millis.is_none() && !iteration_limited && total_iterations.is_none() {
+            return Err(ScenarioError::Missing("duration or iterations"));
+        }
+
+        let ramp_down =
+            Ramp::from_scenario(scenario, "ramp-down").map_err(ScenarioError::Invalid)?;
+
+        // Groups without a fixed count share the scenario's clients
+        let weighted = groups.iter().any(|group| group.clients().is_none());
+        if weighted && clients_size.is_none() {
+            return Err(ScenarioError::Missing("clients"));
+        }
+
+        // Ids follow the interleaved order, so the pool mixes the groups
+        let order = group::interleave(&group::split_clients(&groups, clients_size));
+        let mut clients = Vec::with_capacity(order.len());
+        let mut protocols = Vec::with_capacity(groups.len());
+
+        for (i, group) in groups.iter().enumerate() {
+            let ids: Vec<usize> = (0..order.len()).filter(|id| order[*id] == i).collect();
+            let protocol = registry
+                .get(group.protocol())
+                .ok_or_else(|| ScenarioError::UnknownProtocol(group.protocol().to_owned()))?;
+
+            clients.extend(create_clients(protocol.as_ref(), &ids, i, group.scenario_map())?);
+            protocols.push(protocol);
+        }
+
+        clients.sort_by_key(|(id, _)| *id);
+        let clients = clients.into_iter().map(|(_, client)| client).collect();
+
+        Ok(Self {
+            groups,
+            protocols,
+            ramp_down,
             duration_millis,
+            total_iterations,
+            stages,
+            arrival_rate,
             clients,
-            scenario_map,
-            tx,
-        }
+            report_file: scenario["report-file"].as_str().map(str::to_owned),
+            progress: false,
+            signals: false,
+        })
     }
 
+    /// Runs all phases and prints the report.
     pub async fn execute(&self) {
-        self.pretest().await;
-        self.testloop().await;
-        // self.posttest();
-        // self.teardown();
+        print!("{}", self.run().await);
+    }
+
+    /// Runs all phases and returns the report, exported to the scenario's
+    /// `report-file` if it has one. When the scenario handles signals, on
+    /// Ctrl-C or SIGTERM the clients are stopped and posttest, teardown and
+    /// the report still run, for the elapsed portion.
+    pub async fn run(&self) -> Report {
+        let mut shutdown = self.signals.then(utils::signal::watch_shutdown);
+        let mut started = None;
+        // Counted in place so an aborted run still reports them
+        let mut arrivals = self.arrival_rate.as_ref().map(|_| ArrivalReport {
+            dropped: 0,
+            clients_used: 0,
+            clients: self.clients.len(),
+        });
+        // Kept here so the test loops can still be waited for when the run
+        // is aborted
+        let mut tasks: Tasks = self.clients.iter().map(|_| None).collect();
+
+        let aborted = tokio::select! {
+            _ = async {
+                self.pretest().await;
+                started = Some(Instant::now());
+                self.testloop(arrivals.as_mut(), &mut tasks).await;
+            } => false,
+            _ = async {
+                match &mut shutdown {
+                    Some(shutdown) => shutdown.requested().await,
+                    None => std::future::pending().await,
+                }
+            } => true,
+        };
+        drop(shutdown);
+
+        let elapsed = started.map(|started| started.elapsed()).unwrap_or_default();
+
+        if aborted {
+            for virtual_client in self.clients.iter() {
+                virtual_client.stop();
+            }
+
+            self.join_stopped(&mut tasks).await;
+        }
+
+        self.posttest().await;
+        self.teardown().await;
+
+        let report = self.report(elapsed, aborted, arrivals).await;
+        if let Some(report_file) = &self.report_file {
+            if let Err(error) = report.export(report_file) {
+                eprintln!("Couldn't export the report to {report_file}: {error}");
+            }
+        }
+
+        report
     }
 
     async fn pretest(&self) {
         let mut tasks = Vec::with_capacity(self.clients.len());
 
-        self.clients.iter().for_each(|client| {
-            let task = client.clone().pretest();
+        self.clients.iter().for_each(|virtual_client| {
+            let task = virtual_client.client.pretest();
             tasks.push(task);
         });
 
         let _result = futures::future::join_all(tasks).await;
     }
 
-    async fn testloop(&self) {
-        let mut tasks = Vec::with_capacity(self.clients.len());
+    async fn posttest(&self) {
+        let tasks: Vec<_> = self
+            .clients
+            .iter()
+            .map(|virtual_client| virtual_client.client.posttest())
+            .collect();
+
+        futures::future::join_all(tasks).await;
+    }
 
-        let interval = utils::file::get_interval(&self.scenario_map);
-        let time_offset = interval / self.clients.len() as u64;
+    async fn teardown(&self) {
+        let tasks: Vec<_> = self
+            .clients
+            .iter()
+            .map(|virtual_client| virtual_client.client.teardown())
+            .collect();
 
-        let total_start_time = Instant::now();
+        futures::future::join_all(tasks).await;
+    }
 
-        self.clients.iter().for_each(|client| {
-            thread::sleep(Duration::from_millis(time_offset));
+    /// Waits for the test loops still running after a stop, which end once
+    /// their in-flight work is done or interrupted and their client has shut
+    /// down, for at most the longest grace period and shutdown of the groups.
+    async fn join_stopped(&self, tasks: &mut Tasks) {
+        let stop_time = self
+            .groups
+            .iter()
+            .zip(&self.protocols)
+            .map(|(group, protocol)| {
+                let grace_period = utils::file::get_grace_period(group.scenario_map());
+                Duration::from_millis(grace_period) + protocol.shutdown_timeout()
+            })
+            .max()
+            .unwrap_or_default();
+
+        // Finished tasks may already have been waited for by the test loop
+        let running = tasks.iter_mut().flatten().filter(|task| !task.is_finished());
+        let timeout = stop_time + STOP_MARGIN;
+
+        let _ = tokio::time::timeout(timeout, futures::future::join_all(running)).await;
+    }
 
-            let task = client.clone().test_loop();
-            tasks.push(task);
-        });
+    /// Indices of the clients in group `group`.
+    fn members(&self, group: usize) -> Vec<usize> {
+        (0..self.clients.len())
+            .filter(|i| self.clients[*i].group == group)
+            .collect()
+    }
 
-        let timer = utils::time::create_timer(self.duration_millis, self.tx.clone());
+    /// Resolves once the test loop's duration has passed.
+    fn timer(&self, duration_millis: u128) -> Timer {
+        utils::time::create_timer(duration_millis, self.progress)
+    }
 
-        futures::future::join_all(tasks).await;
-        timer.await.unwrap();
-
-        let mut steps_vec: Vec<(u128, usize)> = Vec::new(); // (time, count)
-
-        for client in self.clients.iter() {
-            let client_data = client.client_data();
-            for (i, step) in client_data.lock().await.steps().iter().enumerate() {
-                if let Some(step_values) = steps_vec.get_mut(i) {
-                    step_values.0 += step.time();
-                    step_values.1 += step.count();
-                } else {
-                    steps_vec.insert(i, (0, 0));
+    /// Runs the clients, counting what happened to the iterations into
+    /// `arrivals` when they arrive at a rate.
+    async fn testloop(&self, arrivals: Option<&mut ArrivalReport>, tasks: &mut Tasks) {
+        if let Some(total_iterations) = self.total_iterations {
+            let budget = Arc::new(AtomicUsize::new(total_iterations));
+
+            for virtual_client in self.clients.iter() {
+                let client_data = virtual_client.client.client_data();
+                client_data.lock().await.set_budget(Some(budget.clone()));
+            }
+        }
+
+        match (&self.arrival_rate, arrivals, &self.stages) {
+            (Some(arrival_rate), Some(arrivals), _) => {
+                self.run_arrival_rate(arrival_rate, arrivals, tasks).await
+            }
+            (_, _, Some(stages)) => self.run_stages(stages, tasks).await,
+            _ => self.run_ramped(tasks).await,
+        }
+    }
+
+    /// Starts the clients over the ramp-up, runs until the duration has passed
+    /// or the clients have run their iterations and then retires them over the
+    /// ramp-down, last started first.
+    async fn run_ramped(&self, tasks: &mut Tasks) {
+        let mut started = Vec::with_capacity(self.clients.len());
+
+        let start = tokio::time::Instant::now();
+        let end = self
+            .duration_millis
+            .map(|duration| start + Duration::from_millis(duration as u64));
+
+        let mut timer = self.duration_millis.map(|duration| self.timer(duration));
+
+        // Each group ramps up over its own ramp, all starting together
+        let mut starts: Vec<(Duration, usize)> = Vec::with_capacity(self.clients.len());
+        for (i, group) in self.groups.iter().enumerate() {
+            let members = self.members(i);
+
+            for (index, client) in members.iter().enumerate() {
+                starts.push((group.ramp_up().offset(index, members.len()), *client));
+            }
+        }
+        starts.sort_by_key(|(offset, _)| *offset);
+
+        for (offset, client) in starts {
+            let start_at = start + offset;
+            if end.is_some_and(|end| start_at >= end) {
+                break;
+            }
+
+            tokio::time::sleep_until(start_at).await;
+            tasks[client] = Some(self.clients[client].start());
+            started.push(client);
+        }
+
+        let mut finished = futures::future::join_all(tasks.iter_mut().flatten());
+
+        let timed_out = match &mut timer {
+            Some(timer) => tokio::select! {
+                _ = timer => true,
+                _ = &mut finished => false,
+            },
+            None => {
+                (&mut finished).await;
+                false
+            }
+        };
+
+        if !timed_out {
+            return;
+        }
+
+        let end = tokio::time::Instant::now();
+        for (i, client) in started.iter().rev().enumerate() {
+            tokio::time::sleep_until(end + self.ramp_down.offset(i, started.len())).await;
+            self.clients[*client].stop();
+        }
+
+        finished.await;
+    }
+
+    /// Follows the stages by starting idle clients from the pool and retiring
+    /// the most recently started ones, checking the target every tick.
+    async fn run_stages(&self, stages: &Stages, tasks: &mut Tasks) {
+        let mut active: Vec<usize> = Vec::new();
+
+        let start = tokio::time::Instant::now();
+        let timer = self.timer(stages.total_duration().as_millis());
+        let mut ticker = tokio::time::interval(STAGE_TICK);
+
+        loop {
+            let elapsed = ticker.tick().await.duration_since(start);
+            if elapsed >= stages.total_duration() {
+                break;
+            }
+
+            let target = stages.target_at(elapsed).round() as usize;
+
+            while active.len() < target {
+                // Retired clients can only start again once their loop has ended
+                let idle = (0..self.clients.len()).find(|i| {
+                    !active.contains(i) && tasks[*i].as_ref().is_none_or(|t| t.is_finished())
+                });
+
+                match idle {
+                    Some(i) => {
+                        tasks[i] = Some(self.clients[i].start());
+                        active.push(i);
+                    }
+                    None => break,
                 }
             }
+
+            while active.len() > target {
+                let i = active.pop().unwrap();
+                self.clients[i].stop();
+            }
         }
 
-        for (i, step) in steps_vec.iter().enumerate() {
-            let avg_response_time = step.0 as f64 / step.1 as f64;
-            let requests_per_second = (step.1 as f64 / total_start_time.elapsed().as_secs_f64()) as u32;
-            println!("Step #{}: {:.2} ms, {} req/sec", i, avg_response_time, requests_per_second);
+        for i in active {
+            self.clients[i].stop();
         }
 
-        // utils::print::print_conclusion(total_start_time, total_response_count, total_response_time);
+        timer.await;
+        futures::future::join_all(tasks.iter_mut().flatten()).await;
     }
 
-    // fn teardown(&self) {}
+    /// Hands out iterations at the configured rate to free clients, starting
+    /// more clients up to the maximum when none is free. Iterations that find
+    /// no client are dropped, not queued, so they can't hide a slow server.
+    async fn run_arrival_rate(
+        &self,
+        arrival_rate: &ArrivalRate,
+        report: &mut ArrivalReport,
+        tasks: &mut Tasks,
+    ) {
+        let (iterations, iterations_rx) = tokio::sync::mpsc::unbounded_channel();
+        let free = Arc::new(AtomicIsize::new(0));
+        let arrivals = Arrivals::new(iterations_rx, free.clone());
+
+        for virtual_client in self.clients.iter() {
+            let client_data = virtual_client.client.client_data();
+            client_data.lock().await.set_arrivals(Some(arrivals.clone()));
+        }
 
-    // fn posttest(&self) {}
-}
+        // Clients are started in order, the first `started` are running
+        let mut started = arrival_rate.pre_allocated();
+        for (task, virtual_client) in tasks.iter_mut().zip(&self.clients[..started]) {
+            *task = Some(virtual_client.start());
+        }
 
-// TODO fix recurrent code
-fn create_http_clients(
-    clients_size: usize,
-    host: &String,
-    port: u16,
-    scenario_map: &Value,
-    tx: &Sender<bool>,
-) -> Vec<Arc<dyn TestClient>> {
-    let mut clients = Vec::with_capacity(clients_size);
+        let start = tokio::time::Instant::now();
+        let timer = self.timer(arrival_rate.duration().as_millis());
+        let mut ticker = tokio::time::interval(ARRIVAL_TICK);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let mut last_tick = start;
+        let mut due = 0.0;
+        report.clients_used = started;
+
+        loop {
+            let now = ticker.tick().await;
+            let elapsed = now.duration_since(start);
+            if elapsed >= arrival_rate.duration() {
+                break;
+            }
+
+            let rate = arrival_rate.rate_at(elapsed);
+            due += rate * now.duration_since(last_tick).as_secs_f64();
+            last_tick = now;
+
+            while due >= 1.0 {
+                due -= 1.0;
+                // Iterations that fell due during the tick are dated back to when
+                let intended = now - Duration::from_secs_f64(due / rate);
+
+                let claimed = free
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |free| {
+                        (free > 0).then_some(free - 1)
+                    })
+                    .is_ok();
+
+                if !claimed {
+                    if started == self.clients.len() {
+                        report.dropped += 1;
+                        continue;
+                    }
+
+                    // The new client takes this iteration once it is running
+                    free.fetch_sub(1, Ordering::SeqCst);
+                    tasks[started] = Some(self.clients[started].start());
+                    started += 1;
+                    report.clients_used = started;
+                }
 
-    for i in 0..clients_size {
-        let client: Arc<dyn TestClient> = Arc::new(TestHttpClient::new(
-            i,
-            host,
-            port,
-            scenario_map.clone(),
-            tx.subscribe(),
-        ));
+                let _ = iterations.send(intended);
+            }
+        }
+
+        for virtual_client in self.clients[..started].iter() {
+            virtual_client.stop();
+        }
 
-        clients.push(client);
+        timer.await;
+        futures::future::join_all(tasks.iter_mut().flatten()).await;
+    }
+
+    async fn report(
+        &self,
+        elapsed: Duration,
+        aborted: bool,
+        arrivals: Option<ArrivalReport>,
+    ) -> Report {
+        let mut groups = Vec::with_capacity(self.groups.len());
+
+        for (i, group) in self.groups.iter().enumerate() {
+            let protocol = self.protocols[i].as_ref();
+            groups.push(self.group_report(i, group, protocol).await);
+        }
+
+        Report {
+            elapsed,
+            aborted,
+            arrivals,
+            groups,
+        }
+    }
+
+    async fn group_report(
+        &self,
+        index: usize,
+        group: &Group,
+        protocol: &dyn Protocol,
+    ) -> GroupReport {
+        let members = self.members(index);
+        let mut steps_vec: Vec<Step> = Vec::new();
+        let mut metrics = Metrics::default();
+
+        for client in members.iter() {
+            let client_data = self.clients[*client].client.client_data();
+            let client_data = client_data.lock().await;
+            for (i, step) in client_data.steps().iter().enumerate() {
+                match steps_vec.get_mut(i) {
+                    Some(total) => total.merge(step),
+                    None => steps_vec.push(step.clone()),
+                }
+            }
+
+            metrics.merge(client_data.metrics().clone());
+        }
+
+        // Metrics come in the order the protocol declares them
+        let declared = protocol.metrics();
+        let position = |name: &str| {
+            declared.iter().position(|metric| match metric.split_once('{') {
+                Some((prefix, _)) => name.starts_with(prefix),
+                None => name == *metric,
+            })
+        };
+        let mut metrics: Vec<_> = metrics
+            .iter()
+            .map(|(name, metric)| (name.clone(), metric.clone()))
+            .collect();
+        metrics.sort_by_key(|(name, _)| position(name).unwrap_or(declared.len()));
+
+        GroupReport {
+            name: group.name().map(str::to_owned),
+            protocol: group.protocol().to_owned(),
+            clients: members.len(),
+            steps: steps_vec,
+            metrics,
+        }
     }
 
-    clients
 }
 
-fn create_mqtt_clients(
-    clients_size: usize,
-    host: &String,
-    port: u16,
-    scenario_map: &Value,
-    tx: &Sender<bool>,
-) -> Vec<Arc<dyn TestClient>> {
-    let mut clients = Vec::with_capacity(clients_size);
+/// Reads `./scenarios/<name>.yml`.
+pub(super) fn load_scenario(scenario_name: &str) -> Result<Value, ScenarioError> {
+    let path = format!("./scenarios/{scenario_name}.yml");
 
-    for i in 0..clients_size {
-        let client: Arc<dyn TestClient> = Arc::new(TestMqttClient::new(
-            i,
-            host,
-            port,
-            scenario_map.clone(),
-            tx.subscribe(),
-        ));
+    utils::file::load_yaml(&path).map_err(|error| ScenarioError::File {
+        error: error.to_string(),
+        path,
+    })
+}
 
-        clients.push(client);
+/// Creates the clients of a group, paired with their ids.
+fn create_clients(
+    protocol: &dyn Protocol,
+    ids: &[usize],
+    group: usize,
+    scenario_map: &Value,
+) -> Result<Vec<(usize, VirtualClient)>, ScenarioError> {
+    // Groups can talk to different hosts over different protocols
+    let scenario = &scenario_map["scenario"];
+    let host = scenario["host"].as_str().ok_or(ScenarioError::Missing("host"))?;
+    let port = scenario["port"].as_u64().ok_or(ScenarioError::Missing("port"))? as u16;
+
+    // The scenario and the steps are checked once, before any client runs
+    protocol.parse_scenario(scenario).map_err(|error| {
+        ScenarioError::Invalid(format!("Invalid {} scenario: {error}", protocol.name()))
+    })?;
+    let steps = protocol
+        .parse_steps(&scenario["testloop"]["steps"])
+        .map_err(ScenarioError::Invalid)?;
+    for phase in ["pretest", "posttest"] {
+        protocol
+            .parse_steps(&scenario[phase]["steps"])
+            .map_err(ScenarioError::Invalid)?;
     }
 
-    clients
+    let clients = ids
+        .iter()
+        .map(|&id| {
+            let (stop, rx) = watch::channel(false);
+            let client =
+                protocol.create_client(id, host, port, scenario_map.clone(), steps.clone(), rx);
+
+            (id, VirtualClient { client, stop, group })
+        })
+        .collect();
+
+    Ok(clients)
 }
@@ -1,3 +1,9 @@
+pub mod registry;
+pub mod test_grpc_client;
 pub mod test_http_client;
 pub mod test_client;
-pub mod test_mqtt_client;
\ No newline at end of file
+pub mod test_coap_client;
+pub mod test_mqtt_client;
+pub mod test_redis_client;
+pub mod test_socket_client;
+pub mod test_websocket_client;
\ No newline at end of file
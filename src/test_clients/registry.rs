@@ -0,0 +1,108 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use serde_yaml::Value;
+use tokio::sync::watch;
+
+use crate::clients::socket_client::Transport;
+
+use super::{
+    test_client::{Step, TestClient},
+    test_coap_client::CoapProtocol,
+    test_grpc_client::GrpcProtocol,
+    test_http_client::HttpProtocol,
+    test_mqtt_client::MqttProtocol,
+    test_redis_client::RedisProtocol,
+    test_socket_client::SocketProtocol,
+    test_websocket_client::WebsocketProtocol,
+};
+
+/// Factory of one protocol's clients, registered under the name scenarios
+/// use as `protocol`.
+pub trait Protocol: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Checks a step, the value under a `step` key, is one this protocol's
+    /// clients can run.
+    fn parse_step(&self, step: &Value) -> Result<Step, String>;
+
+    /// Checks the keys of the `scenario` section its clients read, e.g. the
+    /// files a transport needs, before any client is created.
+    fn parse_scenario(&self, _scenario: &Value) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Names of the measurements besides steps its clients report, in the
+    /// order they are reported. `{...}` stands for a part filled in at runtime.
+    fn metrics(&self) -> &'static [&'static str];
+
+    /// Time a stopped client's test loop may take past the grace period to
+    /// wind down, e.g. to disconnect from the server.
+    fn shutdown_timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient>;
+
+    /// Parses a list of steps, failing on the first the protocol can't run.
+    fn parse_steps(&self, steps: &Value) -> Result<Vec<Step>, String> {
+        steps
+            .as_sequence()
+            .map(|steps| steps.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|step| {
+                self.parse_step(&step["step"])
+                    .map_err(|error| format!("Invalid {} step: {error}", self.name()))
+            })
+            .collect()
+    }
+}
+
+/// Protocols available to scenarios, by name.
+pub struct Registry {
+    protocols: BTreeMap<&'static str, Arc<dyn Protocol>>,
+}
+
+impl Registry {
+    /// A registry without any protocol, not even the built-in ones.
+    pub fn empty() -> Self {
+        Self {
+            protocols: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a protocol, replacing one registered under the same name.
+    pub fn register(&mut self, protocol: impl Protocol + 'static) -> &mut Self {
+        self.protocols.insert(protocol.name(), Arc::new(protocol));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Protocol>> {
+        self.protocols.get(name).cloned()
+    }
+}
+
+impl Default for Registry {
+    /// The built-in protocols.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register(HttpProtocol)
+            .register(MqttProtocol)
+            .register(WebsocketProtocol)
+            .register(GrpcProtocol::default())
+            .register(CoapProtocol)
+            .register(RedisProtocol)
+            .register(SocketProtocol::new(Transport::Tcp))
+            .register(SocketProtocol::new(Transport::Udp));
+        registry
+    }
+}
@@ -1,13 +1,36 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicIsize, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+use async_trait::async_trait;
+use hdrhistogram::Histogram;
 use serde_yaml::Value;
-use tokio::sync::{broadcast::Receiver, Mutex};
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, watch, Mutex},
+    time::Instant,
+};
+
+use crate::utils;
+
+/// Significant figures kept by the latency histograms.
+const HISTOGRAM_PRECISION: u8 = 3;
 
 #[derive(Debug, Clone)]
 pub struct Step {
     step: Value,
     time: u128,
     count: usize,
+    /// Requests aborted because the client was stopped
+    interrupted: usize,
+    /// Latency in microseconds from when each request was actually sent
+    raw: Histogram<u64>,
+    /// Latency in microseconds from when each request was meant to be sent
+    corrected: Histogram<u64>,
 }
 
 impl Step {
@@ -15,7 +38,10 @@ impl Step {
         Self {
             step,
             time: 0,
-            count: 0
+            count: 0,
+            interrupted: 0,
+            raw: Histogram::new(HISTOGRAM_PRECISION).unwrap(),
+            corrected: Histogram::new(HISTOGRAM_PRECISION).unwrap(),
         }
     }
 
@@ -31,6 +57,10 @@ impl Step {
         self.count += 1;
     }
 
+    pub fn add_counts(&mut self, count: usize) {
+        self.count += count;
+    }
+
     pub fn time(&self) -> u128 {
         self.time
     }
@@ -38,51 +68,602 @@ impl Step {
     pub fn count(&self) -> usize {
         self.count
     }
+
+    pub fn interrupt(&mut self) {
+        self.interrupted += 1;
+    }
+
+    pub fn interrupted(&self) -> usize {
+        self.interrupted
+    }
+
+    /// Records a request of `iteration` that took `latency` from being sent.
+    /// The corrected latency adds how late the iteration started on its
+    /// schedule. Closed-loop iterations have none, so there it is the raw one.
+    pub fn record(&mut self, latency: Duration, iteration: &Iteration) {
+        self.add_time(latency.as_millis());
+        self.add_count();
+
+        self.raw.record(latency.as_micros() as u64).unwrap();
+        self.corrected
+            .record((latency + iteration.lag()).as_micros() as u64)
+            .unwrap();
+    }
+
+    /// Records a latency outside of any iteration, e.g. of a metric.
+    pub fn observe(&mut self, latency: Duration) {
+        self.add_time(latency.as_millis());
+        self.add_count();
+        self.raw.record(latency.as_micros() as u64).unwrap();
+    }
+
+    pub fn raw(&self) -> &Histogram<u64> {
+        &self.raw
+    }
+
+    pub fn corrected(&self) -> &Histogram<u64> {
+        &self.corrected
+    }
+
+    pub fn merge(&mut self, other: &Step) {
+        self.time += other.time;
+        self.count += other.count;
+        self.interrupted += other.interrupted;
+        self.raw.add(&other.raw).unwrap();
+        self.corrected.add(&other.corrected).unwrap();
+    }
+}
+
+/// Start of an iteration compared to the pacing schedule.
+#[derive(Debug, Clone, Copy)]
+pub struct Iteration {
+    intended: Instant,
+    started: Instant,
+}
+
+impl Iteration {
+    /// How late the iteration started.
+    pub fn lag(&self) -> Duration {
+        self.started.saturating_duration_since(self.intended)
+    }
+}
+
+/// Measurements that don't belong to a step, e.g. reconnects, keyed by name
+#[derive(Debug, Default, Clone)]
+pub struct Metrics(BTreeMap<String, Step>);
+
+impl Metrics {
+    pub fn record(&mut self, name: &str, time: u128) {
+        let metric = self.metric(name);
+
+        metric.add_time(time);
+        metric.add_count();
+    }
+
+    /// Adds an amount, e.g. of bytes, to a metric's total.
+    pub fn add(&mut self, name: &str, amount: usize) {
+        self.metric(name).add_counts(amount);
+    }
+
+    /// Records a latency that is reported with percentiles, like a step's.
+    pub fn record_latency(&mut self, name: &str, latency: Duration) {
+        self.metric(name).observe(latency);
+    }
+
+    fn metric(&mut self, name: &str) -> &mut Step {
+        self.0
+            .entry(name.to_owned())
+            .or_insert_with(|| Step::new(Value::String(name.to_owned())))
+    }
+
+    pub fn merge(&mut self, other: Metrics) {
+        for (name, other) in other.0 {
+            match self.0.get_mut(&name) {
+                Some(metric) => metric.merge(&other),
+                None => {
+                    self.0.insert(name, other);
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Step)> {
+        self.0.iter()
+    }
+}
+
+/// Iterations handed out by an arrival-rate executor. A client waiting for
+/// one counts as free, the executor claims a free client for every iteration
+/// it hands out and grows the pool when there is none.
+#[derive(Debug, Clone)]
+pub struct Arrivals {
+    iterations: Arc<Mutex<UnboundedReceiver<Instant>>>,
+    free: Arc<AtomicIsize>,
+}
+
+impl Arrivals {
+    pub fn new(iterations: UnboundedReceiver<Instant>, free: Arc<AtomicIsize>) -> Self {
+        Self {
+            iterations: Arc::new(Mutex::new(iterations)),
+            free,
+        }
+    }
+
+    /// Waits for an iteration as a free client. A client that stops waiting
+    /// before one arrives is no longer counted as free.
+    async fn next(&self) -> Option<Instant> {
+        let waiting = Waiting::new(&self.free);
+        let intended = self.iterations.lock().await.recv().await;
+
+        // The executor claimed this client when it sent the iteration
+        if intended.is_some() {
+            waiting.claimed();
+        }
+
+        intended
+    }
+}
+
+/// A client counted as free while it waits for an iteration.
+struct Waiting<'a> {
+    free: &'a AtomicIsize,
+    claimed: bool,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(free: &'a AtomicIsize) -> Self {
+        free.fetch_add(1, Ordering::SeqCst);
+        Self {
+            free,
+            claimed: false,
+        }
+    }
+
+    fn claimed(mut self) {
+        self.claimed = true;
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if !self.claimed {
+            self.free.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Stop signal of a client. It stays set until the client is started again,
+/// so it can't be missed, and leaves in-flight work a grace period to finish.
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    stop: watch::Receiver<bool>,
+    grace_period: Duration,
+    /// End of the grace period, set when the stop is first noticed
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    pub fn new(stop: watch::Receiver<bool>, grace_period: Duration) -> Self {
+        Self {
+            stop,
+            grace_period,
+            deadline: None,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stop.borrow()
+    }
+
+    /// Resolves once the client is told to stop.
+    pub async fn stopped(&self) {
+        let mut stop = self.stop.clone();
+
+        while !*stop.borrow_and_update() {
+            if stop.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Resolves once in-flight work has to be aborted, the grace period after
+    /// the client is told to stop. The grace period runs once, not again for
+    /// every call.
+    pub async fn interrupted(&mut self) {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => {
+                self.stopped().await;
+                *self.deadline.insert(Instant::now() + self.grace_period)
+            }
+        };
+
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
+/// Runs the steps of a protocol's client.
+#[async_trait]
+pub trait RunStep: Send {
+    /// Runs step `index` of the phase and returns when its latency started,
+    /// `None` when it failed and isn't counted.
+    async fn run_step(&mut self, index: usize, step: &Value) -> Option<Instant>;
+}
+
+/// A part of a client's run, named like its key in the scenario.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Pretest,
+    TestLoop,
+    Posttest,
+}
+
+impl Phase {
+    pub fn key(self) -> &'static str {
+        match self {
+            Phase::Pretest => "pretest",
+            Phase::TestLoop => "testloop",
+            Phase::Posttest => "posttest",
+        }
+    }
+
+    /// The phase's steps in the scenario, each the value under its `step` key.
+    fn steps(self, scenario_map: &Value) -> impl Iterator<Item = &Value> {
+        scenario_map["scenario"][self.key()]["steps"]
+            .as_sequence()
+            .map(|steps| steps.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|step| &step["step"])
+    }
+}
+
+/// What a protocol builds from the steps of each phase once, e.g. requests
+/// with their payloads encoded, in the order of the steps.
+pub struct Phases<T> {
+    pretest: Vec<T>,
+    test_loop: Vec<T>,
+    posttest: Vec<T>,
+}
+
+impl<T> Phases<T> {
+    /// Builds the steps of every phase, those of the test loop from its
+    /// parsed `steps`.
+    pub fn new(scenario_map: &Value, steps: &[Step], build: impl Fn(&Value) -> T) -> Self {
+        Self {
+            pretest: Phase::Pretest.steps(scenario_map).map(&build).collect(),
+            test_loop: steps.iter().map(|step| build(step.step())).collect(),
+            posttest: Phase::Posttest.steps(scenario_map).map(&build).collect(),
+        }
+    }
+
+    pub fn get(&self, phase: Phase) -> &[T] {
+        match phase {
+            Phase::Pretest => &self.pretest,
+            Phase::TestLoop => &self.test_loop,
+            Phase::Posttest => &self.posttest,
+        }
+    }
 }
 
 pub struct TestClientData {
     pub steps: Vec<Step>,
-    rx: Receiver<bool>,
+    pub metrics: Metrics,
+    cancellation: Cancellation,
     interval: u64,
+    /// Fixed time per iteration, takes precedence over the interval
+    pacing: Option<Duration>,
+    next_due: Option<Instant>,
+    /// Iterations left for this client
+    iterations: Option<usize>,
+    /// Iterations left for all clients together
+    budget: Option<Arc<AtomicUsize>>,
+    arrivals: Option<Arrivals>,
     scenario_map: Value,
-    id: usize
+    id: usize,
 }
 
 impl TestClientData {
-    pub fn new(scenario_map: Value, steps: Vec<Step>, rx: Receiver<bool>, interval: u64, id: usize) -> Self {
+    pub fn new(
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+        interval: u64,
+        id: usize,
+    ) -> Self {
+        let pacing = utils::file::get_pacing(&scenario_map);
+        let iterations = utils::file::get_iterations(&scenario_map);
+        let grace_period = utils::file::get_grace_period(&scenario_map);
+
         Self {
             steps,
-            rx,
+            metrics: Metrics::default(),
+            cancellation: Cancellation::new(stop, Duration::from_millis(grace_period)),
             interval,
+            pacing: (pacing != 0).then(|| Duration::from_millis(pacing)),
+            next_due: None,
+            iterations,
+            budget: None,
+            arrivals: None,
             scenario_map,
-            id
+            id,
+        }
+    }
+
+    /// Lets an executor decide when iterations start instead of the interval.
+    pub fn set_arrivals(&mut self, arrivals: Option<Arrivals>) {
+        self.arrivals = arrivals;
+    }
+
+    /// Shares a budget of iterations between clients.
+    pub fn set_budget(&mut self, budget: Option<Arc<AtomicUsize>>) {
+        self.budget = budget;
+    }
+
+    /// Takes one iteration from this client's count and the shared budget,
+    /// false once either is used up.
+    fn claim_iteration(&mut self) -> bool {
+        if self.iterations == Some(0) {
+            return false;
+        }
+
+        if let Some(budget) = &self.budget {
+            let claimed = budget
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok();
+
+            if !claimed {
+                return false;
+            }
+        }
+
+        if let Some(iterations) = &mut self.iterations {
+            *iterations -= 1;
+        }
+
+        true
+    }
+
+    /// Waits until the next iteration is due and returns when it was meant
+    /// to start, or `None` once the client is told to stop or has run all
+    /// its iterations.
+    pub async fn next_iteration(&mut self) -> Option<Iteration> {
+        let iteration = self.wait_for_iteration().await;
+
+        if iteration.is_none() {
+            // A retired client starts a fresh schedule when started again
+            self.next_due = None;
+        }
+
+        iteration
+    }
+
+    async fn wait_for_iteration(&mut self) -> Option<Iteration> {
+        if self.cancellation.is_stopped() || !self.claim_iteration() {
+            return None;
+        }
+
+        let cancellation = &self.cancellation;
+
+        match &self.arrivals {
+            Some(arrivals) => {
+                let intended = tokio::select! {
+                    intended = arrivals.next() => intended?,
+                    _ = cancellation.stopped() => return None,
+                };
+
+                Some(Iteration {
+                    intended,
+                    started: Instant::now(),
+                })
+            }
+            None if self.pacing.is_some() => {
+                // Iterations that overrun the pacing start late, not shifted
+                let intended = *self.next_due.get_or_insert_with(Instant::now);
+                self.next_due = Some(intended + self.pacing.unwrap());
+
+                tokio::select! {
+                    _ = tokio::time::sleep_until(intended) => {}
+                    _ = cancellation.stopped() => return None,
+                }
+
+                Some(Iteration {
+                    intended,
+                    started: Instant::now(),
+                })
+            }
+            None => {
+                let interval = Duration::from_millis(self.interval);
+
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = cancellation.stopped() => return None,
+                }
+
+                // The interval is the pace iterations are meant to start at,
+                // so one that runs longer makes the following ones late.
+                // Without an interval there is no schedule to be late on
+                let started = Instant::now();
+                let intended = match self.next_due {
+                    Some(due) if self.interval > 0 => due,
+                    _ => started,
+                };
+                self.next_due = Some(intended + interval);
+
+                Some(Iteration { intended, started })
+            }
         }
     }
 
-    pub fn interval(&self) -> u64 {
-        self.interval
+    /// Runs iterations of the test loop until the client is stopped or has
+    /// run all of them. No step starts once the client is stopped, the one in
+    /// flight is aborted and counted as interrupted when the grace period is
+    /// over.
+    pub async fn run_test_loop(&mut self, runner: &mut impl RunStep) {
+        let mut cancellation = self.cancellation();
+
+        'iterations: while let Some(iteration) = self.next_iteration().await {
+            for (index, step) in self.steps.iter_mut().enumerate() {
+                if cancellation.is_stopped() {
+                    break 'iterations;
+                }
+
+                tokio::select! {
+                    started = runner.run_step(index, step.step()) => {
+                        if let Some(started) = started {
+                            step.record(started.elapsed(), &iteration);
+                        }
+                    }
+                    _ = cancellation.interrupted() => {
+                        step.interrupt();
+                        break 'iterations;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the iterations of the test loop, see [`Self::run_test_loop`], or
+    /// the pretest or posttest steps once.
+    pub async fn run_phase(&mut self, phase: Phase, runner: &mut impl RunStep) {
+        if phase == Phase::TestLoop {
+            return self.run_test_loop(runner).await;
+        }
+
+        for (index, step) in phase.steps(&self.scenario_map).enumerate() {
+            runner.run_step(index, step).await;
+        }
     }
 
     pub fn steps(&self) -> &Vec<Step> {
         &self.steps
     }
 
-    pub fn rx(&self) -> &Receiver<bool> {
-        &self.rx
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
     }
 
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
 
     pub fn scenario_map(&self) -> &Value {
         &self.scenario_map
     }
 }
 
-// TODO temporary type for test result
-pub type TestResult = (u32, u128);
-
-
-pub trait TestClient: Send {
+pub trait TestClient: Send + Sync {
     fn pretest(&self) -> tokio::task::JoinHandle<()>;
     fn test_loop(&self) -> tokio::task::JoinHandle<()>;
+    fn posttest(&self) -> tokio::task::JoinHandle<()>;
+    /// Releases what pretest, test loop or posttest left open.
+    fn teardown(&self) -> tokio::task::JoinHandle<()>;
     fn client_data(&self) -> Arc<Mutex<TestClientData>>;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_time_iterations_are_not_corrected() {
+        let started = Instant::now();
+        let iteration = Iteration {
+            intended: started,
+            started,
+        };
+        let mut step = Step::new(Value::Null);
+
+        step.record(Duration::from_millis(35), &iteration);
+
+        assert_eq!(step.corrected().len(), 1);
+        assert_eq!(
+            step.corrected().value_at_quantile(0.5),
+            step.raw().value_at_quantile(0.5)
+        );
+    }
+
+    #[test]
+    fn late_iterations_add_their_lag() {
+        let intended = Instant::now();
+        let iteration = Iteration {
+            intended,
+            started: intended + Duration::from_millis(20),
+        };
+        let mut step = Step::new(Value::Null);
+
+        step.record(Duration::from_millis(35), &iteration);
+
+        assert!(step.raw().equivalent(step.raw().max(), 35_000));
+        assert!(step.corrected().equivalent(step.corrected().max(), 55_000));
+    }
+
+    /// Sleeps through every step.
+    struct SlowRunner(Duration);
+
+    #[async_trait]
+    impl RunStep for SlowRunner {
+        async fn run_step(&mut self, _index: usize, _step: &Value) -> Option<Instant> {
+            let start_time = Instant::now();
+            tokio::time::sleep(self.0).await;
+            Some(start_time)
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_that_stop_waiting_are_no_longer_free() {
+        let (iterations, iterations_rx) = tokio::sync::mpsc::unbounded_channel();
+        let free = Arc::new(AtomicIsize::new(0));
+        let arrivals = Arrivals::new(iterations_rx, free.clone());
+
+        let waited = tokio::time::timeout(Duration::from_millis(10), arrivals.next()).await;
+        assert!(waited.is_err());
+        assert_eq!(free.load(Ordering::SeqCst), 0);
+
+        let waiting = tokio::spawn(async move { arrivals.next().await });
+        while free.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // Claimed and handed an iteration like the executor does
+        free.fetch_sub(1, Ordering::SeqCst);
+        let intended = Instant::now();
+        iterations.send(intended).unwrap();
+
+        assert_eq!(waiting.await.unwrap(), Some(intended));
+        assert_eq!(free.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn steps_slower_than_the_interval_are_corrected() {
+        let scenario_map: Value = serde_yaml::from_str(
+            r#"
+scenario:
+  testloop:
+    interval: 5ms
+    iterations: 5
+"#,
+        )
+        .unwrap();
+        let (_stop_tx, stop) = watch::channel(false);
+        let steps = vec![Step::new(Value::Null)];
+        let mut client_data = TestClientData::new(scenario_map, steps, stop, 5, 0);
+
+        client_data
+            .run_test_loop(&mut SlowRunner(Duration::from_millis(20)))
+            .await;
+
+        // Each iteration starts a step's time later than the interval meant
+        let step = &client_data.steps()[0];
+        assert_eq!(step.count(), 5);
+        let raw = step.raw().value_at_quantile(0.99);
+        let corrected = step.corrected().value_at_quantile(0.99);
+        assert!(corrected >= raw + 60_000, "corrected {corrected} raw {raw}");
+    }
+}
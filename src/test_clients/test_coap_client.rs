@@ -0,0 +1,565 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde_yaml::Value;
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    clients::coap_client::{self, Block, CoapClient, CoapError, Response},
+    utils,
+};
+
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, Phase, Phases, RunStep, Step, TestClient, TestClientData},
+};
+
+const DEFAULT_ACK_TIMEOUT: &str = "2s";
+const DEFAULT_MAX_RETRANSMIT: u32 = 4;
+const DEFAULT_TIMEOUT: &str = "10s";
+
+/// Requests to resources of a CoAP server over UDP, and observations of them.
+pub struct CoapProtocol;
+
+impl Protocol for CoapProtocol {
+    fn name(&self) -> &'static str {
+        "coap"
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        match (step["path"].as_str(), step["observe"].as_str()) {
+            (Some(_), None) => {
+                method_code(step["method"].as_str().unwrap_or("GET"))?;
+            }
+            (None, Some(_)) => {
+                if step["notifications"].is_null() && step["duration"].is_null() {
+                    return Err(format!("observe needs notifications or a duration in {step:?}"));
+                }
+            }
+            _ => return Err(format!("expected one of path or observe in {step:?}")),
+        }
+
+        if let Some(size) = step["block-size"].as_u64() {
+            if !size.is_power_of_two() || !(16..=1024).contains(&size) {
+                return Err(format!("block-size isn't a power of two from 16 to 1024 in {step:?}"));
+            }
+        }
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &[
+            "response {code}",
+            "coap-error",
+            "retransmission",
+            "block",
+            "notification",
+            "notification-gap",
+            "timeout",
+            "reset",
+            "connection-error",
+        ]
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        Arc::new(TestCoapClient::new(id, host, port, scenario_map, steps, stop))
+    }
+}
+
+pub struct TestCoapClient {
+    client: Arc<Mutex<CoapClient>>,
+    /// The client's steps with their options and payloads built
+    requests: Arc<Phases<Request>>,
+    client_data: Arc<Mutex<TestClientData>>,
+}
+
+/// Runs the requests of a phase of a client.
+struct RequestRunner<'a> {
+    client: &'a mut CoapClient,
+    requests: &'a [Request],
+    metrics: Metrics,
+}
+
+#[async_trait]
+impl RunStep for RequestRunner<'_> {
+    async fn run_step(&mut self, index: usize, _step: &Value) -> Option<Instant> {
+        let start_time = Instant::now();
+
+        self.requests[index]
+            .run(self.client, &mut self.metrics)
+            .await
+            .then_some(start_time)
+    }
+}
+
+struct Request {
+    confirmable: bool,
+    code: u8,
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+    block_size: Option<usize>,
+    observe: Option<Observe>,
+}
+
+/// How long an observation is kept up.
+struct Observe {
+    notifications: Option<u64>,
+    duration: Option<Duration>,
+}
+
+impl TestCoapClient {
+    pub fn new(
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        let scenario = &scenario_map["scenario"];
+        let millis = |key: &str, default: &str| {
+            let time = scenario[key].as_str().unwrap_or(default);
+            Duration::from_millis(utils::time::string_to_millis_u128(time) as u64)
+        };
+
+        let client = Arc::new(Mutex::new(CoapClient::new(
+            &format!("{host}:{port}"),
+            id,
+            millis("ack-timeout", DEFAULT_ACK_TIMEOUT),
+            scenario["max-retransmit"]
+                .as_u64()
+                .map(|max| max as u32)
+                .unwrap_or(DEFAULT_MAX_RETRANSMIT),
+            millis("timeout", DEFAULT_TIMEOUT),
+        )));
+
+        let requests = Arc::new(Phases::new(&scenario_map, &steps, |step| {
+            Request::new(step, id)
+        }));
+
+        let interval = utils::file::get_interval(&scenario_map);
+
+        let client_data = Arc::new(Mutex::new(TestClientData::new(
+            scenario_map,
+            steps,
+            stop,
+            interval,
+            id,
+        )));
+
+        Self {
+            client,
+            requests,
+            client_data,
+        }
+    }
+
+    /// Runs the requests of `phase` from the client's socket.
+    fn run(&self, phase: Phase) -> JoinHandle<()> {
+        let client_data = self.client_data.clone();
+        let client = self.client.clone();
+        let requests = self.requests.clone();
+
+        tokio::spawn(async move {
+            let mut client_data = client_data.lock().await;
+            let mut client = client.lock().await;
+            let mut runner = RequestRunner {
+                client: &mut client,
+                requests: requests.get(phase),
+                metrics: Metrics::default(),
+            };
+
+            client_data.run_phase(phase, &mut runner).await;
+            client_data.metrics.merge(runner.metrics);
+        })
+    }
+}
+
+impl TestClient for TestCoapClient {
+    fn pretest(&self) -> JoinHandle<()> {
+        self.run(Phase::Pretest)
+    }
+
+    fn test_loop(&self) -> JoinHandle<()> {
+        self.run(Phase::TestLoop)
+    }
+
+    fn posttest(&self) -> JoinHandle<()> {
+        self.run(Phase::Posttest)
+    }
+
+    /// Closes the client's socket.
+    fn teardown(&self) -> JoinHandle<()> {
+        let client = self.client.clone();
+
+        tokio::spawn(async move {
+            client.lock().await.close();
+        })
+    }
+
+    fn client_data(&self) -> Arc<Mutex<TestClientData>> {
+        self.client_data.clone()
+    }
+}
+
+impl Request {
+    /// Builds a step's request, `{id}` in its path, query and payload replaced
+    /// by the client's number.
+    fn new(step: &Value, id: usize) -> Self {
+        let fill = |text: &str| text.replace("{id}", &id.to_string());
+
+        let path = fill(step["path"].as_str().or(step["observe"].as_str()).unwrap());
+        let mut options: Vec<(u16, Vec<u8>)> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| (coap_client::URI_PATH, segment.as_bytes().to_vec()))
+            .collect();
+
+        if let Some(query) = step["query"].as_str() {
+            options.extend(
+                fill(query)
+                    .split('&')
+                    .map(|query| (coap_client::URI_QUERY, query.as_bytes().to_vec())),
+            );
+        }
+
+        if let Some(format) = step["content-format"].as_u64() {
+            options.push((
+                coap_client::CONTENT_FORMAT,
+                coap_client::encode_uint(format as u32),
+            ));
+        }
+
+        let observe = step["observe"].as_str().map(|_| Observe {
+            notifications: step["notifications"].as_u64(),
+            duration: step["duration"].as_str().map(|duration| {
+                Duration::from_millis(utils::time::string_to_millis_u128(duration) as u64)
+            }),
+        });
+
+        Self {
+            confirmable: step["confirmable"].as_bool().unwrap_or(true),
+            code: method_code(step["method"].as_str().unwrap_or("GET")).unwrap(),
+            options,
+            payload: step["payload"].as_str().map(fill).unwrap_or_default().into_bytes(),
+            block_size: step["block-size"].as_u64().map(|size| size as usize),
+            observe,
+        }
+    }
+
+    /// Runs the step, false when it got no response or a client or server
+    /// error.
+    async fn run(&self, client: &mut CoapClient, metrics: &mut Metrics) -> bool {
+        match &self.observe {
+            Some(observe) => self.observe(client, observe, metrics).await,
+            None => self.exchange(client, metrics).await.is_some(),
+        }
+    }
+
+    /// Sends the request, in blocks if its payload is larger than the block
+    /// size, and fetches the rest of a response sent in blocks.
+    async fn exchange(&self, client: &mut CoapClient, metrics: &mut Metrics) -> Option<Response> {
+        let response = match self.block_size {
+            Some(size) if self.payload.len() > size => client
+                .request_blockwise(
+                    self.confirmable,
+                    self.code,
+                    self.options.clone(),
+                    self.payload.clone(),
+                    size,
+                )
+                .await
+                .map(|(response, retransmissions)| Response {
+                    retransmissions,
+                    ..response
+                }),
+            Some(size) => {
+                // Asks for a response in blocks of this size as well
+                let mut options = self.options.clone();
+                options.push((coap_client::BLOCK2, block(0, size)));
+                let token = client.new_token();
+                client
+                    .request(self.confirmable, self.code, token, options, self.payload.clone())
+                    .await
+            }
+            None => {
+                let token = client.new_token();
+                client
+                    .request(
+                        self.confirmable,
+                        self.code,
+                        token,
+                        self.options.clone(),
+                        self.payload.clone(),
+                    )
+                    .await
+            }
+        };
+
+        let mut response = answered(response, metrics)?;
+
+        // The following blocks of a response are asked for one by one, with
+        // the request's method and options but not its payload, which the
+        // server already has
+        while let Some(received) = response.message.option(coap_client::BLOCK2).map(Block::decode) {
+            if !received.more {
+                break;
+            }
+
+            let mut options = self.options.clone();
+            options.push((coap_client::BLOCK2, block(received.num + 1, received.size)));
+            let token = client.new_token();
+            let next = client
+                .request(self.confirmable, self.code, token, options, Vec::new())
+                .await;
+
+            response = answered(next, metrics)?;
+            metrics.record("block", 0);
+        }
+
+        succeeded(response, metrics)
+    }
+
+    /// Registers as an observer, counts notifications until there were
+    /// enough or the duration has passed, then deregisters.
+    async fn observe(&self, client: &mut CoapClient, observe: &Observe, metrics: &mut Metrics) -> bool {
+        let start_time = Instant::now();
+        let token = client.new_token();
+
+        let mut options = self.options.clone();
+        options.push((coap_client::OBSERVE, coap_client::encode_uint(0)));
+        let registered = client
+            .request(self.confirmable, coap_client::GET, token.clone(), options, Vec::new())
+            .await;
+
+        let response = answered(registered, metrics).and_then(|response| succeeded(response, metrics));
+        let response = match response {
+            Some(response) => response,
+            None => return false,
+        };
+
+        // A server that can't observe the resource answers like to a GET
+        if response.message.option(coap_client::OBSERVE).is_none() {
+            return true;
+        }
+
+        let deadline = observe
+            .duration
+            .map(|duration| start_time + duration)
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(86_400 * 365));
+        let mut last = Instant::now();
+        let mut received = 0;
+
+        while observe.notifications.is_none_or(|notifications| received < notifications) {
+            match client.next(&token, deadline).await {
+                Ok(Some(_)) => {
+                    let now = Instant::now();
+                    metrics.record("notification", 0);
+                    metrics.record_latency("notification-gap", now - last);
+                    last = now;
+                    received += 1;
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    metrics.record("connection-error", 0);
+                    return false;
+                }
+            }
+        }
+
+        let mut options = self.options.clone();
+        options.push((coap_client::OBSERVE, coap_client::encode_uint(1)));
+        let deregistered = client
+            .request(self.confirmable, coap_client::GET, token, options, Vec::new())
+            .await;
+        answered(deregistered, metrics);
+
+        true
+    }
+}
+
+/// The response of a request, with its retransmissions and failures recorded.
+fn answered(response: Result<Response, CoapError>, metrics: &mut Metrics) -> Option<Response> {
+    match response {
+        Ok(response) => {
+            if response.retransmissions > 0 {
+                metrics.add("retransmission", response.retransmissions as usize);
+            }
+            Some(response)
+        }
+        Err(CoapError::Timeout) => {
+            metrics.record("timeout", 0);
+            None
+        }
+        Err(CoapError::Reset) => {
+            metrics.record("reset", 0);
+            None
+        }
+        Err(CoapError::Io) => {
+            metrics.record("connection-error", 0);
+            None
+        }
+    }
+}
+
+/// The response unless its code is of class 4 or 5, a client or server
+/// error. Its code is recorded either way.
+fn succeeded(response: Response, metrics: &mut Metrics) -> Option<Response> {
+    let code = response.message.code;
+    metrics.record(&format!("response {}", coap_client::format_code(code)), 0);
+
+    if code >> 5 >= 4 {
+        metrics.record("coap-error", 0);
+        return None;
+    }
+
+    Some(response)
+}
+
+fn block(num: u32, size: usize) -> Vec<u8> {
+    Block {
+        num,
+        more: false,
+        size,
+    }
+    .encode()
+}
+
+fn method_code(method: &str) -> Result<u8, String> {
+    match method {
+        "GET" => Ok(coap_client::GET),
+        "POST" => Ok(coap_client::POST),
+        "PUT" => Ok(coap_client::PUT),
+        "DELETE" => Ok(coap_client::DELETE),
+        _ => Err(format!("unknown method {method}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use coap_client::{Message, Type};
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+
+    fn response(code: u8) -> Response {
+        Response {
+            message: Message {
+                kind: Type::Acknowledgement,
+                code,
+                message_id: 1,
+                token: Vec::new(),
+                options: Vec::new(),
+                payload: Vec::new(),
+            },
+            retransmissions: 0,
+        }
+    }
+
+    /// Answers every request with its block of `content`, `size` bytes a
+    /// block, and hands each request on.
+    async fn block_server(
+        content: &'static [u8],
+        size: usize,
+    ) -> (String, UnboundedReceiver<Message>) {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let (requests, received) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let blocks: Vec<&[u8]> = content.chunks(size).collect();
+            let mut buffer = [0; 1024];
+
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer).await {
+                let request = Message::decode(&buffer[..len]).unwrap();
+                let num = request
+                    .option(coap_client::BLOCK2)
+                    .map_or(0, |block| Block::decode(block).num);
+                let more = (num as usize) + 1 < blocks.len();
+
+                let response = Message {
+                    kind: Type::Acknowledgement,
+                    code: 0x45,
+                    message_id: request.message_id,
+                    token: request.token.clone(),
+                    options: vec![(coap_client::BLOCK2, Block { num, more, size }.encode())],
+                    payload: blocks[num as usize].to_vec(),
+                };
+                socket.send_to(&response.encode(), peer).await.unwrap();
+                let _ = requests.send(request);
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn response_blocks_are_fetched_with_the_request_method() {
+        let (addr, mut requests) = block_server(&[7; 40], 16).await;
+        let timeout = Duration::from_secs(1);
+        let mut client = CoapClient::new(&addr, 0, timeout, 0, timeout);
+        let step = "{path: '/reports/{id}', method: POST, payload: query, block-size: 16}";
+        let request = Request::new(&serde_yaml::from_str(step).unwrap(), 3);
+        let mut metrics = Metrics::default();
+
+        assert!(request.run(&mut client, &mut metrics).await);
+
+        let mut blocks = Vec::new();
+        while let Ok(request) = requests.try_recv() {
+            let block = Block::decode(request.option(coap_client::BLOCK2).unwrap());
+            let path: Vec<&[u8]> = request
+                .options
+                .iter()
+                .filter(|(number, _)| *number == coap_client::URI_PATH)
+                .map(|(_, segment)| segment.as_slice())
+                .collect();
+
+            assert_eq!(request.code, coap_client::POST);
+            assert_eq!(path, [&b"reports"[..], b"3"]);
+            blocks.push((block.num, request.payload));
+        }
+
+        // The payload went with the first request only
+        assert_eq!(blocks, [(0, b"query".to_vec()), (1, Vec::new()), (2, Vec::new())]);
+        let (_, fetched) = metrics.iter().find(|(name, _)| *name == "block").unwrap();
+        assert_eq!(fetched.count(), 2);
+    }
+
+    #[test]
+    fn client_and_server_errors_fail_the_step() {
+        let mut metrics = Metrics::default();
+
+        assert!(succeeded(response(0x45), &mut metrics).is_some());
+        assert!(succeeded(response(0x84), &mut metrics).is_none());
+        assert!(succeeded(response(0xa0), &mut metrics).is_none());
+
+        let counts: BTreeMap<&str, usize> = metrics
+            .iter()
+            .map(|(name, metric)| (name.as_str(), metric.count()))
+            .collect();
+        assert_eq!(
+            counts,
+            BTreeMap::from([
+                ("coap-error", 2),
+                ("response 2.05", 1),
+                ("response 4.04", 1),
+                ("response 5.00", 1),
+            ])
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{self, Arc},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use serde_yaml::Value;
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    clients::grpc_client::{self, GrpcClient},
+    utils,
+};
+
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, Phase, Phases, RunStep, Step, TestClient, TestClientData},
+};
+
+/// Unary and server-streaming calls of services described by `.proto` files
+/// or a descriptor set, with messages written as YAML or JSON.
+#[derive(Default)]
+pub struct GrpcProtocol {
+    /// Loaded descriptors by the `proto` settings they were loaded from, so
+    /// clients of a group share them
+    pools: sync::Mutex<HashMap<String, DescriptorPool>>,
+}
+
+impl GrpcProtocol {
+    fn pool(&self, proto: &Value) -> DescriptorPool {
+        let key = serde_yaml::to_string(proto).unwrap();
+
+        self.pools
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| load_descriptors(proto))
+            .clone()
+    }
+}
+
+impl Protocol for GrpcProtocol {
+    fn name(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        match step["call"].as_str() {
+            Some(call) if call.contains('/') => {}
+            _ => return Err(format!("expected call: <service>/<method> in {step:?}")),
+        }
+
+        if let Some(message) = step["message"].as_str() {
+            serde_yaml::from_str::<Value>(message).map_err(|error| error.to_string())?;
+        }
+
+        if !step["metadata"].is_null() && step["metadata"].as_mapping().is_none() {
+            return Err(format!("metadata isn't a mapping in {step:?}"));
+        }
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &[
+            "method {method}",
+            "status {code}",
+            "error {code}",
+            "stream-message",
+            "decode-error",
+            "connection-error",
+        ]
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        let pool = self.pool(&scenario_map["scenario"]["proto"]);
+        Arc::new(TestGrpcClient::new(
+            id,
+            host,
+            port,
+            scenario_map,
+            steps,
+            stop,
+            &pool,
+        ))
+    }
+}
+
+pub struct TestGrpcClient {
+    client: Arc<GrpcClient>,
+    /// The client's steps resolved against the descriptors, requests encoded
+    calls: Arc<Phases<Call>>,
+    client_data: Arc<Mutex<TestClientData>>,
+}
+
+/// Runs the calls of a phase of a client.
+struct CallRunner<'a> {
+    client: &'a GrpcClient,
+    calls: &'a [Call],
+    metrics: Metrics,
+}
+
+#[async_trait]
+impl RunStep for CallRunner<'_> {
+    async fn run_step(&mut self, index: usize, _step: &Value) -> Option<Instant> {
+        let start_time = Instant::now();
+
+        self.calls[index]
+            .run(self.client, &mut self.metrics)
+            .await
+            .then_some(start_time)
+    }
+}
+
+struct Call {
+    /// Full method name, e.g. `helloworld.Greeter/SayHello`
+    method: String,
+    path: String,
+    metadata: Vec<(String, String)>,
+    request: Vec<u8>,
+    /// The call's deadline, none without a `timeout`
+    timeout: Option<Duration>,
+    response: MessageDescriptor,
+    server_streaming: bool,
+}
+
+impl TestGrpcClient {
+    pub fn new(
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+        pool: &DescriptorPool,
+    ) -> Self {
+        let client = Arc::new(GrpcClient::new(&format!("{host}:{port}")));
+
+        let calls = Arc::new(Phases::new(&scenario_map, &steps, |step| {
+            Call::new(pool, step, id)
+        }));
+
+        let interval = utils::file::get_interval(&scenario_map);
+
+        let client_data = Arc::new(Mutex::new(TestClientData::new(
+            scenario_map,
+            steps,
+            stop,
+            interval,
+            id,
+        )));
+
+        Self {
+            client,
+            calls,
+            client_data,
+        }
+    }
+
+    /// Makes the calls of `phase` over the client's connection.
+    fn run(&self, phase: Phase) -> JoinHandle<()> {
+        let client_data = self.client_data.clone();
+        let client = self.client.clone();
+        let calls = self.calls.clone();
+
+        tokio::spawn(async move {
+            let mut client_data = client_data.lock().await;
+            let mut runner = CallRunner {
+                client: &client,
+                calls: calls.get(phase),
+                metrics: Metrics::default(),
+            };
+
+            client_data.run_phase(phase, &mut runner).await;
+            client_data.metrics.merge(runner.metrics);
+        })
+    }
+}
+
+impl TestClient for TestGrpcClient {
+    fn pretest(&self) -> JoinHandle<()> {
+        self.run(Phase::Pretest)
+    }
+
+    fn test_loop(&self) -> JoinHandle<()> {
+        self.run(Phase::TestLoop)
+    }
+
+    fn posttest(&self) -> JoinHandle<()> {
+        self.run(Phase::Posttest)
+    }
+
+    /// Nothing to release, the pooled connection closes with the client.
+    fn teardown(&self) -> JoinHandle<()> {
+        tokio::spawn(async move {})
+    }
+
+    fn client_data(&self) -> Arc<Mutex<TestClientData>> {
+        self.client_data.clone()
+    }
+}
+
+impl Call {
+    /// Resolves a step's method and encodes its message, `{id}` in the
+    /// message's strings replaced by the client's number.
+    fn new(pool: &DescriptorPool, step: &Value, id: usize) -> Self {
+        let method = step["call"].as_str().unwrap();
+        let (service_name, method_name) = method.rsplit_once('/').unwrap();
+
+        let descriptor = pool
+            .get_service_by_name(service_name)
+            .unwrap_or_else(|| panic!("Unknown grpc service: {service_name}"))
+            .methods()
+            .find(|descriptor| descriptor.name() == method_name)
+            .unwrap_or_else(|| panic!("Unknown grpc method: {method}"));
+
+        if descriptor.is_client_streaming() {
+            panic!("Client streaming grpc methods aren't supported: {method}");
+        }
+
+        let message = match &step["message"] {
+            Value::String(message) => serde_yaml::from_str(message).unwrap(),
+            Value::Null => Value::Mapping(Default::default()),
+            message => message.clone(),
+        };
+        let request = DynamicMessage::deserialize(descriptor.input(), fill_id(&message, id))
+            .unwrap_or_else(|error| panic!("Invalid grpc message for {method}: {error}"));
+
+        let metadata = step["metadata"]
+            .as_mapping()
+            .map(|metadata| {
+                metadata
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = value.as_str().unwrap().replace("{id}", &id.to_string());
+                        (key.as_str().unwrap().to_owned(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let timeout = step["timeout"]
+            .as_str()
+            .map(|time| Duration::from_millis(utils::time::string_to_millis_u128(time) as u64));
+
+        Self {
+            method: method.to_owned(),
+            path: format!("/{method}"),
+            metadata,
+            request: request.encode_to_vec(),
+            timeout,
+            response: descriptor.output(),
+            server_streaming: descriptor.is_server_streaming(),
+        }
+    }
+
+    /// Makes the call, false when it didn't end with status OK.
+    async fn run(&self, client: &GrpcClient, metrics: &mut Metrics) -> bool {
+        let start_time = Instant::now();
+
+        let response = client
+            .call(&self.path, &self.metadata, &self.request, self.timeout)
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(_) => {
+                metrics.record("connection-error", 0);
+                return false;
+            }
+        };
+
+        let status = grpc_client::status_name(response.status);
+        metrics.record_latency(&format!("method {}", self.method), start_time.elapsed());
+        metrics.record(&format!("status {status}"), 0);
+        if response.status != grpc_client::OK {
+            metrics.record(&format!("error {status}"), 0);
+        }
+
+        for message in response.messages {
+            if self.server_streaming {
+                metrics.record("stream-message", 0);
+            }
+
+            if DynamicMessage::decode(self.response.clone(), message).is_err() {
+                metrics.record("decode-error", 0);
+            }
+        }
+
+        response.status == grpc_client::OK
+    }
+}
+
+/// Loads the services of the scenario's `proto` settings, either `.proto`
+/// files or a descriptor set as written by `protoc --descriptor_set_out`.
+fn load_descriptors(proto: &Value) -> DescriptorPool {
+    let strings = |value: &Value| -> Vec<String> {
+        match value {
+            Value::String(value) => vec![value.clone()],
+            Value::Sequence(values) => values
+                .iter()
+                .map(|value| value.as_str().unwrap().to_owned())
+                .collect(),
+            _ => Vec::new(),
+        }
+    };
+
+    match proto["descriptor-set"].as_str() {
+        Some(path) => {
+            let bytes = std::fs::read(path)
+                .unwrap_or_else(|error| panic!("Can't read descriptor set {path}: {error}"));
+
+            DescriptorPool::decode(bytes.as_slice())
+                .unwrap_or_else(|error| panic!("Invalid descriptors: {error}"))
+        }
+        None => {
+            let files = strings(&proto["files"]);
+            if files.is_empty() {
+                panic!("No proto files or descriptor set specified");
+            }
+
+            // Imports are looked up in the includes, by default the files' directories
+            let mut includes = strings(&proto["includes"]);
+            if includes.is_empty() {
+                includes = files
+                    .iter()
+                    .map(|file| match Path::new(file).parent() {
+                        Some(parent) if parent != Path::new("") => parent.display().to_string(),
+                        _ => ".".to_owned(),
+                    })
+                    .collect();
+            }
+
+            compile_protos(&files, &includes)
+        }
+    }
+}
+
+/// Descriptors of `.proto` files and their imports, parsed in-process so no
+/// `protoc` is needed.
+fn compile_protos(files: &[String], includes: &[String]) -> DescriptorPool {
+    let descriptors = protox::compile(files, includes)
+        .unwrap_or_else(|error| panic!("Can't parse proto files: {error}"));
+
+    DescriptorPool::from_file_descriptor_set(descriptors)
+        .unwrap_or_else(|error| panic!("Invalid descriptors: {error}"))
+}
+
+/// Replaces `{id}` in the strings of a message.
+fn fill_id(message: &Value, id: usize) -> Value {
+    match message {
+        Value::String(text) => Value::String(text.replace("{id}", &id.to_string())),
+        Value::Sequence(values) => values.iter().map(|value| fill_id(value, id)).collect(),
+        Value::Mapping(fields) => Value::Mapping(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), fill_id(value, id)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proto_files_are_parsed_without_protoc() {
+        let proto: Value =
+            serde_yaml::from_str("files: [scenarios/protos/helloworld.proto]").unwrap();
+
+        let pool = load_descriptors(&proto);
+
+        let service = pool.get_service_by_name("helloworld.Greeter").unwrap();
+        assert!(service.methods().any(|method| method.name() == "SayHello"));
+    }
+}
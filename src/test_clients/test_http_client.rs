@@ -1,20 +1,196 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, error::Error, sync::Arc, time::Duration};
 
+use async_trait::async_trait;
 use serde_yaml::Value;
-use tokio::sync::{broadcast::Receiver, Mutex};
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+    time::Instant,
+};
 
 use crate::{
-    clients::{client_trait::HttpClient, custom_http_client::CustomHttpClient, request::Method},
+    clients::{
+        client_trait::HttpClient,
+        custom_http_client::{self, CustomHttpClient},
+        event_stream::EventStream,
+        hyper_http_client::HyperHttpClient,
+        request::Method,
+    },
     utils,
 };
 
-use super::test_client::{TestClient, TestClientData};
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, Phase, RunStep, Step, TestClient, TestClientData},
+};
 
 type Client = Arc<Mutex<dyn HttpClient + Send + Sync>>;
 
+const DEFAULT_POLL_TIMEOUT: &str = "60s";
+
+/// Requests to an `endpoint` of the scenario's host. A step can instead read
+/// an event stream, long-poll the endpoint or post a GraphQL operation to it.
+pub struct HttpProtocol;
+
+impl Protocol for HttpProtocol {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        if step["endpoint"].as_str().is_none() {
+            return Err(format!("missing endpoint in {step:?}"));
+        }
+
+        let kinds = ["sse", "long-poll", "graphql"]
+            .iter()
+            .filter(|kind| !step[**kind].is_null())
+            .count();
+        if kinds > 1 {
+            return Err(format!(
+                "expected one of sse, long-poll or graphql in {step:?}"
+            ));
+        }
+
+        let graphql = &step["graphql"];
+        if !graphql.is_null() {
+            if graphql["query"].as_str().is_none() {
+                return Err(format!("missing graphql query in {step:?}"));
+            }
+
+            if !graphql["extract"].is_null() && graphql["extract"].as_mapping().is_none() {
+                return Err(format!("graphql extract isn't a mapping in {step:?}"));
+            }
+        }
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &[
+            "time-to-first-event",
+            "event-gap",
+            "event",
+            "poll-held",
+            "poll-timeout",
+            "operation {name}",
+            "operation-error {name}",
+            "extract-error",
+            "http-error",
+            "connection-error",
+        ]
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        Arc::new(TestHttpClient::new(
+            id,
+            host,
+            port,
+            scenario_map,
+            steps,
+            stop,
+        ))
+    }
+}
+
+/// Runs the steps of a phase of a client.
+struct StepRunner<'a> {
+    client: &'a mut (dyn HttpClient + Send + Sync),
+    hyper_client: &'a HyperHttpClient,
+    addr: &'a Arc<String>,
+    headers: Arc<String>,
+    /// Values steps fill in for {name}, the client's number and what
+    /// GraphQL steps extracted
+    variables: &'a mut HashMap<String, String>,
+    metrics: Metrics,
+    /// Still set after the loop when a plain request was interrupted
+    in_flight: bool,
+}
+
+#[async_trait]
+impl RunStep for StepRunner<'_> {
+    async fn run_step(&mut self, _index: usize, spec: &Value) -> Option<Instant> {
+        let endpoint = fill(spec["endpoint"].as_str().unwrap(), self.variables);
+        let endpoint = endpoint.as_str();
+        let (hyper_client, addr) = (self.hyper_client, self.addr);
+
+        let start_time = Instant::now();
+        let done = if !spec["sse"].is_null() {
+            read_events(
+                hyper_client,
+                addr,
+                endpoint,
+                &spec["sse"],
+                &mut self.metrics,
+            )
+            .await
+        } else if !spec["long-poll"].is_null() {
+            long_poll(
+                hyper_client,
+                addr,
+                endpoint,
+                &spec["long-poll"],
+                &mut self.metrics,
+            )
+            .await
+        } else if !spec["graphql"].is_null() {
+            post_graphql(
+                hyper_client,
+                addr,
+                endpoint,
+                &spec["graphql"],
+                self.variables,
+                &mut self.metrics,
+            )
+            .await
+        } else {
+            self.in_flight = true;
+            let response = self
+                .client
+                .request(
+                    Method::GET,
+                    addr.clone(),
+                    endpoint.to_owned(),
+                    self.headers.clone(),
+                    None,
+                )
+                .await;
+            self.in_flight = false;
+
+            match response.map(|response| custom_http_client::status(&response)) {
+                Ok(Some(200..=299)) => true,
+                Ok(_) => {
+                    self.metrics.record("http-error", 0);
+                    false
+                }
+                Err(_) => {
+                    self.metrics.record("connection-error", 0);
+                    false
+                }
+            }
+        };
+
+        done.then_some(start_time)
+    }
+}
+
 pub struct TestHttpClient {
     client: Client,
+    /// Requests the custom client can't make: event streams, long polls and
+    /// GraphQL posts
+    hyper_client: Arc<HyperHttpClient>,
     addr: Arc<String>,
+    /// Kept from phase to phase, so the test loop can use what a pretest
+    /// GraphQL step extracted
+    variables: Arc<Mutex<HashMap<String, String>>>,
     client_data: Arc<Mutex<TestClientData>>,
 }
 
@@ -23,80 +199,311 @@ impl TestHttpClient {
         id: usize,
         host: &str,
         port: u16,
-        mut scenario_map: Value,
-        rx: Receiver<bool>,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
     ) -> Self {
         let client: Client = Arc::new(Mutex::new(CustomHttpClient::new()));
 
         let addr = Arc::new(format!("{}:{}", &host, port));
-        let steps = utils::file::get_steps(&mut scenario_map);
         let interval = utils::file::get_interval(&scenario_map);
 
         let client_data = Arc::new(Mutex::new(TestClientData::new(
             scenario_map,
             steps,
-            rx,
+            stop,
             interval,
             id,
         )));
 
         TestHttpClient {
             client,
+            hyper_client: Arc::new(HyperHttpClient::new()),
             addr,
+            variables: Arc::new(Mutex::new(HashMap::from([(
+                "id".to_owned(),
+                id.to_string(),
+            )]))),
             client_data,
         }
     }
-}
 
-impl TestClient for TestHttpClient {
-    fn test_loop(&self) -> tokio::task::JoinHandle<()> {
+    /// Runs the steps of `phase` over the client's connection.
+    fn run(&self, phase: Phase) -> JoinHandle<()> {
         let headers = Arc::new("Host: localhost".to_owned());
         let client_data = self.client_data.clone();
         let client = self.client.clone();
+        let hyper_client = self.hyper_client.clone();
         let addr = self.addr.clone();
+        let variables = self.variables.clone();
 
         tokio::spawn(async move {
+            // The client connects with the first request
             let mut client = client.lock().await;
-            client.connect(addr.clone()).await;
+            let mut variables = variables.lock().await;
 
             let mut client_data = client_data.lock().await;
+            let mut runner = StepRunner {
+                client: &mut *client,
+                hyper_client: &hyper_client,
+                addr: &addr,
+                headers,
+                variables: &mut variables,
+                metrics: Metrics::default(),
+                in_flight: false,
+            };
 
-            while client_data.rx().is_empty() {
-                // TODO Include ramp up
-                if client_data.interval() != 0 {
-                    tokio::time::sleep(Duration::from_millis(client_data.interval())).await;
-                }
-
-                for step in client_data.steps.iter_mut() {
-                    let endpoint = step.step()["endpoint"].as_str().unwrap();
-
-                    let start_time = std::time::Instant::now();
-                    let _resp = client
-                        .request(
-                            Method::GET,
-                            addr.clone(),
-                            endpoint.to_owned(),
-                            headers.clone(),
-                            None,
-                        )
-                        .await
-                        .unwrap();
-
-                    let time = start_time.elapsed().as_millis();
-
-                    step.add_time(time);
-                    step.add_count();
-                }
+            client_data.run_phase(phase, &mut runner).await;
+            // The response to an interrupted request would be read as the
+            // next one's
+            if runner.in_flight {
+                runner.client.disconnect(&addr);
             }
+            client_data.metrics.merge(runner.metrics);
         })
     }
+}
+
+impl TestClient for TestHttpClient {
+    fn pretest(&self) -> JoinHandle<()> {
+        self.run(Phase::Pretest)
+    }
+
+    fn test_loop(&self) -> JoinHandle<()> {
+        self.run(Phase::TestLoop)
+    }
+
+    fn posttest(&self) -> JoinHandle<()> {
+        self.run(Phase::Posttest)
+    }
+
+    /// Closes the connection the steps left open.
+    fn teardown(&self) -> JoinHandle<()> {
+        let client = self.client.clone();
+        let addr = self.addr.clone();
 
-    fn pretest(&self) -> tokio::task::JoinHandle<()> {
-        tokio::spawn(async move {})
+        tokio::spawn(async move {
+            client.lock().await.disconnect(&addr);
+        })
     }
 
     fn client_data(&self) -> Arc<Mutex<TestClientData>> {
         self.client_data.clone()
     }
-    
+}
+
+/// Reads an event stream until it has delivered `events` events, `duration`
+/// has passed or the server ends it. False when it couldn't be read.
+async fn read_events(
+    client: &HyperHttpClient,
+    addr: &str,
+    endpoint: &str,
+    sse: &Value,
+    metrics: &mut Metrics,
+) -> bool {
+    let events = sse["events"].as_u64();
+    let duration = sse["duration"]
+        .as_str()
+        .map(|duration| Duration::from_millis(utils::time::string_to_millis_u128(duration) as u64));
+    let event_type = sse["event"].as_str();
+
+    let start_time = Instant::now();
+    let response = match client.open(addr, endpoint, "text/event-stream").await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(_) => {
+            metrics.record("http-error", 0);
+            return false;
+        }
+        Err(_) => {
+            metrics.record("connection-error", 0);
+            return false;
+        }
+    };
+
+    let mut stream = EventStream::new(response.into_body());
+    let read = async {
+        let mut received = 0;
+        let mut last_event = None;
+
+        while events.is_none_or(|events| received < events) {
+            let event = match stream.next().await {
+                Some(Ok(event)) => event,
+                Some(Err(_)) => {
+                    metrics.record("connection-error", 0);
+                    return false;
+                }
+                None => break,
+            };
+
+            if event_type.is_some_and(|event_type| event_type != event.event) {
+                continue;
+            }
+
+            let now = Instant::now();
+            match last_event {
+                None => metrics.record_latency("time-to-first-event", now - start_time),
+                Some(last_event) => metrics.record_latency("event-gap", now - last_event),
+            }
+            metrics.record("event", 0);
+
+            last_event = Some(now);
+            received += 1;
+        }
+
+        true
+    };
+
+    match duration {
+        Some(duration) => tokio::time::timeout(duration, read).await.unwrap_or(true),
+        None => read.await,
+    }
+}
+
+/// Requests the endpoint `polls` times in a row, each as soon as the previous
+/// answered, and records how long the server held each. A poll the server
+/// holds past the timeout is given up and issued again.
+async fn long_poll(
+    client: &HyperHttpClient,
+    addr: &str,
+    endpoint: &str,
+    long_poll: &Value,
+    metrics: &mut Metrics,
+) -> bool {
+    let polls = long_poll["polls"].as_u64().unwrap_or(1);
+    let timeout = long_poll["timeout"]
+        .as_str()
+        .unwrap_or(DEFAULT_POLL_TIMEOUT);
+    let timeout = Duration::from_millis(utils::time::string_to_millis_u128(timeout) as u64);
+
+    for _ in 0..polls {
+        let start_time = Instant::now();
+        let poll = async {
+            let response = client.open(addr, endpoint, "*/*").await?;
+            let status = response.status();
+            hyper::body::to_bytes(response.into_body()).await?;
+
+            Ok::<_, Box<dyn Error + Send + Sync>>(status)
+        };
+
+        match tokio::time::timeout(timeout, poll).await {
+            Ok(Ok(status)) if status.is_success() => {
+                metrics.record_latency("poll-held", start_time.elapsed())
+            }
+            Ok(Ok(_)) => {
+                metrics.record("http-error", 0);
+                return false;
+            }
+            Ok(Err(_)) => {
+                metrics.record("connection-error", 0);
+                return false;
+            }
+            Err(_) => metrics.record("poll-timeout", 0),
+        }
+    }
+
+    true
+}
+
+/// Posts a GraphQL operation. It failed when the response carries `errors`,
+/// whatever the status, or a value to extract from `data` is missing.
+async fn post_graphql(
+    client: &HyperHttpClient,
+    addr: &str,
+    endpoint: &str,
+    graphql: &Value,
+    variables: &mut HashMap<String, String>,
+    metrics: &mut Metrics,
+) -> bool {
+    let operation = graphql["operation"].as_str();
+    let name = operation.unwrap_or("anonymous");
+
+    let mut body = serde_json::json!({
+        // Only the variables are filled in, braces are the query's own syntax
+        "query": graphql["query"].as_str().unwrap(),
+        "variables": serde_json::to_value(fill_value(&graphql["variables"], variables)).unwrap(),
+    });
+    if let Some(operation) = operation {
+        body["operationName"] = operation.into();
+    }
+
+    let start_time = Instant::now();
+    let (status, response) = match client
+        .post_json(addr, endpoint, body.to_string().into_bytes())
+        .await
+    {
+        Ok(response) => response,
+        Err(_) => {
+            metrics.record("connection-error", 0);
+            return false;
+        }
+    };
+    metrics.record_latency(&format!("operation {name}"), start_time.elapsed());
+
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap_or_default();
+    let has_errors = response["errors"]
+        .as_array()
+        .is_some_and(|errors| !errors.is_empty());
+
+    if has_errors {
+        metrics.record(&format!("operation-error {name}"), 0);
+        return false;
+    }
+
+    if !status.is_success() {
+        metrics.record("http-error", 0);
+        return false;
+    }
+
+    for (variable, path) in graphql["extract"].as_mapping().into_iter().flatten() {
+        let found = path
+            .as_str()
+            .unwrap()
+            .split('.')
+            .try_fold(&response["data"], |value, key| match key.parse::<usize>() {
+                Ok(index) => value.get(index),
+                Err(_) => value.get(key),
+            });
+
+        match found {
+            Some(serde_json::Value::String(text)) => {
+                variables.insert(variable.as_str().unwrap().to_owned(), text.clone());
+            }
+            Some(value) if !value.is_null() => {
+                variables.insert(variable.as_str().unwrap().to_owned(), value.to_string());
+            }
+            _ => {
+                metrics.record("extract-error", 0);
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Replaces `{name}` in a text by the variable's value.
+fn fill(text: &str, variables: &HashMap<String, String>) -> String {
+    variables
+        .iter()
+        .fold(text.to_owned(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), value)
+        })
+}
+
+/// Fills in the variables in the strings of a value.
+fn fill_value(value: &Value, variables: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(text) => Value::String(fill(text, variables)),
+        Value::Sequence(values) => values
+            .iter()
+            .map(|value| fill_value(value, variables))
+            .collect(),
+        Value::Mapping(fields) => Value::Mapping(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), fill_value(value, variables)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
 }
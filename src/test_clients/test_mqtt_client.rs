@@ -1,61 +1,385 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use rumqttc::{AsyncClient, EventLoop, MqttOptions};
+use async_trait::async_trait;
+use rumqttc::{
+    AsyncClient, ClientConfig, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS,
+    Request, TlsConfiguration, Transport,
+};
+use rustls::{Certificate, PrivateKey, RootCertStore};
 use serde_yaml::Value;
-use tokio::sync::{broadcast::Receiver, Mutex};
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot, watch, Mutex,
+    },
+    task::JoinHandle,
+    time::Instant,
+};
 
 use crate::utils;
 
-use super::test_client::{Step, TestClient, TestClientData};
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, RunStep, Step, TestClient, TestClientData},
+};
+
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time an await step waits for its message, and other steps for their ack.
+const DEFAULT_STEP_TIMEOUT: &str = "10s";
+
+/// Publishes, awaits, subscribes and unsubscribes over one connection per
+/// client.
+pub struct MqttProtocol;
+
+impl Protocol for MqttProtocol {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        let kinds = ["publish", "await", "subscribe", "unsubscribe"]
+            .iter()
+            .filter(|kind| step[**kind].as_str().is_some())
+            .count();
+
+        if kinds != 1 {
+            return Err(format!(
+                "expected one of publish, await, subscribe or unsubscribe in {step:?}"
+            ));
+        }
+
+        if step["qos"].as_u64().is_some_and(|qos| qos > 2) {
+            return Err(format!("invalid qos in {step:?}"));
+        }
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn parse_scenario(&self, scenario: &Value) -> Result<(), String> {
+        let mut mqtt_options =
+            create_mqtt_options("check".to_owned(), "localhost", 1883, scenario)?;
+        set_session_options(&mut mqtt_options, 0, scenario)
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &[
+            "received {filter}",
+            "connection-error",
+            "reconnect",
+            "backlog-message",
+            "backlog-drain",
+        ]
+    }
+
+    fn shutdown_timeout(&self) -> Duration {
+        DISCONNECT_TIMEOUT
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        Arc::new(TestMqttClient::new(id, host, port, scenario_map, steps, stop))
+    }
+}
 
 pub struct TestMqttClient {
-    client: Arc<Mutex<AsyncClient>>,
-    eventloop: Arc<Mutex<EventLoop>>,
+    client: AsyncClient,
+    /// Owned by the poller while the client is connected and handed back when
+    /// it stops, so polling never waits on the steps or the metrics.
+    eventloop: Arc<std::sync::Mutex<Option<EventLoop>>>,
+    poller: Arc<Mutex<Option<Poller>>>,
     client_data: Arc<Mutex<TestClientData>>,
+    settings: Arc<PollerSettings>,
 }
 
-impl TestMqttClient {
-    pub fn new(id: usize, host: &str, port: u16, scenario_map: Value, rx: Receiver<bool>) -> Self {
-        let id_str = format!("mqtt_client_{}", &id);
-        let mut mqtt_options = MqttOptions::new(id_str, host, port);
+/// A running poll task. Incoming publishes on awaited topics are forwarded to
+/// the steps, its own measurements are returned together with the eventloop.
+struct Poller {
+    stop: watch::Sender<bool>,
+    subscriptions: UnboundedSender<SubscriptionChange>,
+    acks: UnboundedSender<AwaitedAck>,
+    incoming: UnboundedReceiver<Received>,
+    /// When messages were received, by the awaited filter they match, until
+    /// an await step on that filter takes them
+    buffered: HashMap<String, VecDeque<Instant>>,
+    task: JoinHandle<(EventLoop, Metrics)>,
+}
+
+struct Received {
+    topic: String,
+    at: Instant,
+}
+
+/// Keeps the poller's filters in line with the subscribe and unsubscribe steps,
+/// so received messages can be attributed to the filter they arrived on.
+enum SubscriptionChange {
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+/// Requests whose steps last until the broker acknowledges them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AckedRequest {
+    Publish,
+    Subscribe,
+    Unsubscribe,
+}
+
+/// A step waiting for the ack of the request it is about to send.
+struct AwaitedAck {
+    request: AckedRequest,
+    done: oneshot::Sender<()>,
+}
 
-        let credentials = scenario_map
-            .get("scenario")
-            .unwrap()
-            .get("credentials")
-            .unwrap();
-        let username = credentials.get("username").unwrap().as_str().unwrap();
-        let password = credentials.get("password").unwrap().as_str().unwrap();
-        mqtt_options.set_credentials(username, password);
+/// Matches acks to the steps waiting for them. A request only gets its packet
+/// id when it goes out, which happens in the order the steps sent them.
+struct Acks {
+    awaited: UnboundedReceiver<AwaitedAck>,
+    /// Sent by a step but not out yet
+    queued: VecDeque<AwaitedAck>,
+    /// Out and waiting for the ack, by packet id
+    in_flight: HashMap<u16, oneshot::Sender<()>>,
+}
+
+impl Acks {
+    fn new(awaited: UnboundedReceiver<AwaitedAck>) -> Self {
+        Self {
+            awaited,
+            queued: VecDeque::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    fn sent(&mut self, request: AckedRequest, pkid: u16) {
+        // Publishes sent again after a reconnect keep their packet id
+        if pkid != 0 && self.in_flight.contains_key(&pkid) {
+            return;
+        }
+
+        while let Ok(awaited) = self.awaited.try_recv() {
+            self.queued.push_back(awaited);
+        }
 
-        let (client, eventloop) = AsyncClient::new(mqtt_options, 10);
+        // Steps that gave up before their request went out don't wait anymore
+        self.queued.retain(|awaited| !awaited.done.is_closed());
 
-        let steps = scenario_map["scenario"]["testloop"]["steps"]
-            .as_sequence()
-            .unwrap()
-            .clone();
+        let position = self
+            .queued
+            .iter()
+            .position(|awaited| awaited.request == request);
+        let awaited = match position.and_then(|position| self.queued.remove(position)) {
+            Some(awaited) => awaited,
+            None => return,
+        };
+
+        // QoS 0 publishes have no ack, they are done once they are out
+        if pkid == 0 {
+            let _ = awaited.done.send(());
+        } else {
+            self.in_flight.insert(pkid, awaited.done);
+        }
+    }
+
+    fn acked(&mut self, pkid: u16) {
+        if let Some(done) = self.in_flight.remove(&pkid) {
+            let _ = done.send(());
+        }
+    }
+}
+
+struct PollerSettings {
+    id: usize,
+    channel_capacity: usize,
+    reconnect_storm: Option<ReconnectStorm>,
+    awaited_filters: Vec<String>,
+}
+
+/// Runs the steps of a phase against a connected client.
+struct StepRunner<'a> {
+    client: &'a AsyncClient,
+    poller: &'a mut Poller,
+    id: String,
+    /// Start of the current iteration. Awaits only match messages received
+    /// since, older ones would match at once.
+    since: Instant,
+}
+
+/// Schedule from the scenario's `reconnect-storm` key. The first `clients`
+/// clients go offline together at `at` and come back after `offline`, with
+/// their reconnects spread evenly over `spread`.
+#[derive(Debug, Clone)]
+struct ReconnectStorm {
+    clients: usize,
+    at: Duration,
+    offline: Duration,
+    spread: Duration,
+    repeat: Option<Duration>,
+    graceful: bool,
+}
+
+impl ReconnectStorm {
+    fn from_scenario(scenario: &Value, id: usize) -> Option<Self> {
+        let storm = scenario.get("reconnect-storm")?;
+
+        let clients = storm["clients"].as_u64().unwrap() as usize;
+        if id >= clients {
+            return None;
+        }
+
+        let duration = |key: &str, default: &str| {
+            let time = storm[key].as_str().unwrap_or(default);
+            Duration::from_millis(utils::time::string_to_millis_u128(time) as u64)
+        };
+
+        let graceful = match storm["mode"].as_str().unwrap_or("disconnect") {
+            "disconnect" => true,
+            "drop" => false,
+            mode => panic!("Unknown reconnect-storm mode: {mode}"),
+        };
+
+        Some(Self {
+            clients,
+            at: duration("at", "0s"),
+            offline: duration("offline", "0s"),
+            spread: duration("spread", "0s"),
+            repeat: storm["repeat"]
+                .as_str()
+                .map(|time| Duration::from_millis(utils::time::string_to_millis_u128(time) as u64)),
+            graceful,
+        })
+    }
+
+    fn offline_time(&self, id: usize) -> Duration {
+        self.offline + self.spread.mul_f64(id as f64 / self.clients as f64)
+    }
+}
+
+/// Tracks the queued backlog a persistent session delivers after a reconnect.
+/// Messages published before the session came back count as backlog.
+struct Backlog {
+    connected_at: Instant,
+    connected_millis: u128,
+    drain_time: Option<u128>,
+}
+
+impl Backlog {
+    fn new() -> Self {
+        Self {
+            connected_at: Instant::now(),
+            connected_millis: unix_millis(),
+            drain_time: None,
+        }
+    }
+
+    /// Returns false once live messages arrive, i.e. the backlog is drained.
+    fn receive(&mut self, payload: &[u8], metrics: &mut Metrics) -> bool {
+        let sent_millis = match sent_millis(payload) {
+            Some(sent_millis) => sent_millis,
+            None => return true,
+        };
+
+        if sent_millis >= self.connected_millis {
+            return false;
+        }
+
+        metrics.record("backlog-message", unix_millis().saturating_sub(sent_millis));
+        self.drain_time = Some(self.connected_at.elapsed().as_millis());
+
+        true
+    }
+
+    fn finish(self, metrics: &mut Metrics) {
+        if let Some(drain_time) = self.drain_time {
+            metrics.record("backlog-drain", drain_time);
+        }
+    }
+}
 
-        let mut steps_vec = Vec::with_capacity(steps.len());
+impl TestMqttClient {
+    pub fn new(
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        let scenario = &scenario_map["scenario"];
+        let client_id = scenario["client-id"]
+            .as_str()
+            .unwrap_or("mqtt_client_{id}")
+            .replace("{id}", &id.to_string());
+        let mut mqtt_options = create_mqtt_options(client_id, host, port, scenario)
+            .and_then(|mut mqtt_options| {
+                set_session_options(&mut mqtt_options, id, scenario)?;
+                Ok(mqtt_options)
+            })
+            .unwrap_or_else(|error| panic!("Invalid mqtt scenario: {error}"));
 
-        for step in steps {
-            let step = Step::new(step.get("step").unwrap().to_owned());
-            steps_vec.push(step);
+        // Without credentials the client connects anonymously
+        let credentials = &scenario["credentials"];
+        if let (Some(username), Some(password)) =
+            (credentials["username"].as_str(), credentials["password"].as_str())
+        {
+            mqtt_options.set_credentials(username, password);
         }
 
+        let channel_capacity = scenario["channel-capacity"].as_u64().unwrap_or(10) as usize;
+        let (client, eventloop) = AsyncClient::new(mqtt_options, channel_capacity);
+
         let interval = utils::file::get_interval(&scenario_map);
 
+        // Pretest and posttest steps await messages as well
+        let once_steps = ["pretest", "posttest"].into_iter().flat_map(|phase| {
+            scenario[phase]["steps"]
+                .as_sequence()
+                .map(|steps| steps.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|step| &step["step"])
+        });
+        let mut awaited_filters: Vec<String> = steps
+            .iter()
+            .map(Step::step)
+            .chain(once_steps)
+            .filter_map(|step| step["await"].as_str())
+            .map(|filter| filter.replace("{id}", &id.to_string()))
+            .collect();
+        awaited_filters.sort();
+        awaited_filters.dedup();
+
+        let settings = Arc::new(PollerSettings {
+            id,
+            channel_capacity,
+            reconnect_storm: ReconnectStorm::from_scenario(&scenario_map["scenario"], id),
+            awaited_filters,
+        });
+
         let client_data = Arc::new(Mutex::new(TestClientData::new(
             scenario_map,
-            steps_vec,
-            rx,
+            steps,
+            stop,
             interval,
             id,
         )));
 
         Self {
-            client: Arc::new(Mutex::new(client)),
-            eventloop: Arc::new(Mutex::new(eventloop)),
+            client,
+            eventloop: Arc::new(std::sync::Mutex::new(Some(eventloop))),
+            poller: Arc::new(Mutex::new(None)),
             client_data,
+            settings,
         }
     }
 }
@@ -64,89 +388,764 @@ impl TestClient for TestMqttClient {
     fn pretest(&self) -> tokio::task::JoinHandle<()> {
         let client_data = self.client_data.clone();
         let client = self.client.clone();
+        let eventloop = self.eventloop.clone();
+        let poller = self.poller.clone();
+        let settings = self.settings.clone();
 
         tokio::spawn(async move {
             let client_data = client_data.lock().await;
-            let client = client.lock().await;
-
-            let pretest = client_data
-                .scenario_map()
-                .get("scenario")
-                .unwrap()
-                .get("pretest");
-            if pretest.is_none() {
-                return ();
-            }
+            let steps =
+                match client_data.scenario_map()["scenario"]["pretest"]["steps"].as_sequence() {
+                    Some(steps) => steps,
+                    None => return,
+                };
 
-            let subs = pretest
-                .unwrap()
-                .get("subscribe")
-                .unwrap()
-                .as_sequence()
-                .unwrap();
-
-            for sub in subs {
-                let topic = sub.as_str().unwrap();
-                client
-                    .subscribe(topic, rumqttc::QoS::AtLeastOnce)
-                    .await
-                    .unwrap();
+            // Requests only go out while the eventloop is polled
+            let mut poller = poller.lock().await;
+            let poller = poller.get_or_insert_with(|| Poller::start(&eventloop, settings));
+
+            let mut runner = StepRunner {
+                client: &client,
+                poller,
+                id: client_data.id().to_string(),
+                since: Instant::now(),
+            };
+
+            for step in steps {
+                runner.run(&step["step"]).await;
             }
         })
     }
 
     fn test_loop(&self) -> tokio::task::JoinHandle<()> {
-        let client = self.client.clone();
         let client_data = self.client_data.clone();
+        let client = self.client.clone();
+        let eventloop = self.eventloop.clone();
+        let poller = self.poller.clone();
+        let settings = self.settings.clone();
+
+        tokio::spawn(async move {
+            // Locked in the same order as in pretest and posttest
+            let mut client_data = client_data.lock().await;
+            let mut poller_slot = poller.lock().await;
+            let poller = poller_slot.get_or_insert_with(|| Poller::start(&eventloop, settings));
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(10);
+            let mut runner = StepRunner {
+                client: &client,
+                poller,
+                id: client_data.id().to_string(),
+                since: Instant::now(),
+            };
+
+            client_data.run_test_loop(&mut runner).await;
+
+            let poller = poller_slot.take().unwrap();
+            let metrics = poller.stop(&eventloop).await;
+            client_data.metrics.merge(metrics);
+        })
+    }
+
+    fn posttest(&self) -> tokio::task::JoinHandle<()> {
+        let client_data = self.client_data.clone();
+        let client = self.client.clone();
+        let eventloop = self.eventloop.clone();
+        let poller = self.poller.clone();
+        let settings = self.settings.clone();
 
         tokio::spawn(async move {
-            let publish = Value::String("publish".to_owned());
             let client_data = client_data.lock().await;
-            while client_data.rx().is_empty() {
-                for step in client_data.steps() {
-                    let step = step.step().as_mapping().unwrap();
-                    if step.contains_key(&publish) {
-                        let client = client.lock().await;
-                        let _publish = client
-                            .publish(
-                                "some/topic",
-                                rumqttc::QoS::ExactlyOnce,
-                                false,
-                                "hej".as_bytes().to_vec(),
-                            )
-                            .await;
-                        // tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
-                }
-            }
+            let steps =
+                match client_data.scenario_map()["scenario"]["posttest"]["steps"].as_sequence() {
+                    Some(steps) => steps,
+                    None => return,
+                };
 
-            let _stop = client
-                .lock()
-                .await
-                .publish("stop", rumqttc::QoS::ExactlyOnce, false, "stop")
-                .await;
-        });
+            let mut poller = poller.lock().await;
+            let poller = poller.get_or_insert_with(|| Poller::start(&eventloop, settings));
+
+            let mut runner = StepRunner {
+                client: &client,
+                poller,
+                id: client_data.id().to_string(),
+                since: Instant::now(),
+            };
 
+            for step in steps {
+                runner.run(&step["step"]).await;
+            }
+        })
+    }
+
+    /// Stops a poller left running, e.g. by posttest or by a pretest whose
+    /// client never ran the test loop, which disconnects the client.
+    fn teardown(&self) -> tokio::task::JoinHandle<()> {
         let client_data = self.client_data.clone();
         let eventloop = self.eventloop.clone();
-        tokio::spawn(async move {
-            let mut response_count = 0;
+        let poller = self.poller.clone();
 
-            while client_data.lock().await.rx().is_empty() {
-                let mut eventloop = eventloop.lock().await;
+        tokio::spawn(async move {
+            let poller = poller.lock().await.take();
 
-                if let Ok(message) = eventloop.poll().await {
-                    println!("{:?}", message);
-                    response_count += 1;
-                    // if await: wait for publish on topic -> send message on tx
-                }
+            if let Some(poller) = poller {
+                let metrics = poller.stop(&eventloop).await;
+                client_data.lock().await.metrics.merge(metrics);
             }
         })
     }
 
     fn client_data(&self) -> Arc<Mutex<TestClientData>> {
-        todo!()
+        self.client_data.clone()
+    }
+}
+
+impl Poller {
+    /// Moves the eventloop into a new poll task.
+    fn start(
+        eventloop: &std::sync::Mutex<Option<EventLoop>>,
+        settings: Arc<PollerSettings>,
+    ) -> Self {
+        let eventloop = eventloop.lock().unwrap().take().unwrap();
+        let (stop, stop_rx) = watch::channel(false);
+        let (subscriptions, subscriptions_rx) = mpsc::unbounded_channel();
+        let (acks, acks_rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let buffered = settings
+            .awaited_filters
+            .iter()
+            .map(|filter| (filter.clone(), VecDeque::new()))
+            .collect();
+
+        let task = tokio::spawn(poll(
+            eventloop,
+            settings,
+            subscriptions_rx,
+            Acks::new(acks_rx),
+            incoming_tx,
+            stop_rx,
+        ));
+
+        Self {
+            stop,
+            subscriptions,
+            acks,
+            incoming,
+            buffered,
+            task,
+        }
+    }
+
+    /// Waits for a message on `filter` received at `since` or later. Messages
+    /// for the other awaited filters are kept for their await steps, those
+    /// from before `since` are dropped.
+    async fn receive(&mut self, filter: &str, since: Instant) -> Option<Instant> {
+        loop {
+            for buffered in self.buffered.values_mut() {
+                while buffered.front().is_some_and(|at| *at < since) {
+                    buffered.pop_front();
+                }
+            }
+
+            if let Some(at) = self.buffered.get_mut(filter)?.pop_front() {
+                return Some(at);
+            }
+
+            let received = self.incoming.recv().await?;
+            for (awaited, buffered) in self.buffered.iter_mut() {
+                if filter_matches(awaited, &received.topic) {
+                    buffered.push_back(received.at);
+                }
+            }
+        }
+    }
+
+    /// Stops the poll task and hands the eventloop back for a later start.
+    async fn stop(self, eventloop: &std::sync::Mutex<Option<EventLoop>>) -> Metrics {
+        let _ = self.stop.send(true);
+        let (stopped, metrics) = self.task.await.unwrap();
+        *eventloop.lock().unwrap() = Some(stopped);
+
+        metrics
     }
-}
\ No newline at end of file
+}
+
+/// Drives the eventloop until stopped. Reconnect storms are played out here
+/// as well, since going offline means not polling.
+async fn poll(
+    mut eventloop: EventLoop,
+    settings: Arc<PollerSettings>,
+    mut subscriptions: UnboundedReceiver<SubscriptionChange>,
+    mut acks: Acks,
+    incoming: UnboundedSender<Received>,
+    mut stop: watch::Receiver<bool>,
+) -> (EventLoop, Metrics) {
+    let mut metrics = Metrics::default();
+    let mut filters: Vec<String> = Vec::new();
+    let reconnect_storm = settings.reconnect_storm.as_ref();
+
+    let mut next_storm = reconnect_storm.map(|storm| Instant::now() + storm.at);
+    let mut reconnect_started: Option<Instant> = None;
+    let mut backlog: Option<Backlog> = None;
+
+    loop {
+        let storm_at = next_storm.unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+
+        let event = tokio::select! {
+            _ = stop.changed() => break,
+            Some(change) = subscriptions.recv() => {
+                match change {
+                    SubscriptionChange::Subscribe(filter) => filters.push(filter),
+                    SubscriptionChange::Unsubscribe(filter) => filters.retain(|f| *f != filter),
+                }
+                continue;
+            }
+            event = eventloop.poll() => event,
+            _ = tokio::time::sleep_until(storm_at), if next_storm.is_some() => {
+                let storm = reconnect_storm.unwrap();
+                if let Some(backlog) = backlog.take() {
+                    backlog.finish(&mut metrics);
+                }
+
+                tokio::select! {
+                    _ = stop.changed() => break,
+                    _ = go_offline(&mut eventloop, storm.graceful, settings.channel_capacity) => {}
+                }
+
+                tokio::select! {
+                    _ = stop.changed() => break,
+                    _ = tokio::time::sleep(storm.offline_time(settings.id)) => {}
+                }
+
+                reconnect_started = Some(Instant::now());
+                next_storm = storm.repeat.map(|repeat| storm_at + repeat);
+                continue;
+            }
+        };
+
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                if let Some(started) = reconnect_started.take() {
+                    metrics.record("reconnect", started.elapsed().as_millis());
+                    backlog = Some(Backlog::new());
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Publish(pkid))) => acks.sent(AckedRequest::Publish, pkid),
+            Ok(Event::Outgoing(Outgoing::Subscribe(pkid))) => {
+                acks.sent(AckedRequest::Subscribe, pkid)
+            }
+            Ok(Event::Outgoing(Outgoing::Unsubscribe(pkid))) => {
+                acks.sent(AckedRequest::Unsubscribe, pkid)
+            }
+            Ok(Event::Incoming(Packet::PubAck(ack))) => acks.acked(ack.pkid),
+            Ok(Event::Incoming(Packet::PubComp(ack))) => acks.acked(ack.pkid),
+            Ok(Event::Incoming(Packet::SubAck(ack))) => acks.acked(ack.pkid),
+            Ok(Event::Incoming(Packet::UnsubAck(ack))) => acks.acked(ack.pkid),
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                // Latency is only known when the payload is the send time,
+                // other messages are just counted
+                let latency = sent_millis(&publish.payload).map(|sent_millis| {
+                    Duration::from_millis(unix_millis().saturating_sub(sent_millis) as u64)
+                });
+
+                for filter in filters.iter() {
+                    if filter_matches(filter, &publish.topic) {
+                        let name = format!("received {filter}");
+                        match latency {
+                            Some(latency) => metrics.record_latency(&name, latency),
+                            None => metrics.add(&name, 1),
+                        }
+                    }
+                }
+
+                if let Some(mut current) = backlog.take() {
+                    if current.receive(&publish.payload, &mut metrics) {
+                        backlog = Some(current);
+                    } else {
+                        current.finish(&mut metrics);
+                    }
+                }
+
+                if settings
+                    .awaited_filters
+                    .iter()
+                    .any(|filter| filter_matches(filter, &publish.topic))
+                {
+                    let _ = incoming.send(Received {
+                        topic: publish.topic,
+                        at: Instant::now(),
+                    });
+                }
+            }
+            Ok(_) => {}
+            Err(_) => {
+                metrics.record("connection-error", 0);
+                // The next poll reconnects, don't hammer the broker while it is down
+                tokio::time::sleep(RECONNECT_BACKOFF).await;
+            }
+        }
+    }
+
+    if let Some(backlog) = backlog {
+        backlog.finish(&mut metrics);
+    }
+
+    go_offline(&mut eventloop, true, settings.channel_capacity).await;
+
+    (eventloop, metrics)
+}
+
+/// Takes the client offline. A graceful disconnect sends DISCONNECT first,
+/// otherwise the socket is just closed as if the network went away.
+async fn go_offline(eventloop: &mut EventLoop, graceful: bool, channel_capacity: usize) {
+    // A broker that is down or slow doesn't hold the client up
+    if graceful {
+        let _ = tokio::time::timeout(DISCONNECT_TIMEOUT, disconnect(eventloop)).await;
+    }
+
+    // A fresh eventloop drops the connection but keeps the request channel of
+    // the AsyncClient, so publishes queue up until the client reconnects.
+    // Publishes still waiting for their acks are sent again after the
+    // reconnect, as rumqttc does when a connection fails.
+    let mut offline = EventLoop::new(eventloop.options.clone(), channel_capacity);
+    offline.requests_tx = eventloop.requests_tx.clone();
+    offline.requests_rx = eventloop.requests_rx.clone();
+
+    let mut pending: Vec<Request> = eventloop.pending.by_ref().collect();
+    pending.extend(eventloop.state.clean());
+    offline.pending = pending.into_iter();
+
+    *eventloop = offline;
+}
+
+/// Sends DISCONNECT and polls until it went out or the connection failed.
+async fn disconnect(eventloop: &mut EventLoop) {
+    let handle = eventloop.handle();
+    let mut sent = false;
+
+    while !sent {
+        // Keep polling, the request channel may be full of publishes
+        tokio::select! {
+            result = handle.send_async(Request::Disconnect) => sent = result.is_ok(),
+            _ = eventloop.poll() => {}
+        }
+    }
+
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+}
+
+impl StepRunner<'_> {
+    /// Runs a single step, returns false when it failed or timed out and
+    /// shouldn't be counted. Publishes, subscribes and unsubscribes last until
+    /// the broker acknowledged them, QoS 0 publishes until they are sent.
+    async fn run(&mut self, step: &Value) -> bool {
+        let timeout = step["timeout"].as_str().unwrap_or(DEFAULT_STEP_TIMEOUT);
+        let timeout = Duration::from_millis(utils::time::string_to_millis_u128(timeout) as u64);
+
+        if let Some(topic) = step["publish"].as_str() {
+            // Without a payload the send time is published, which lets
+            // persistent sessions tell queued messages from live ones
+            let payload = match step["payload"].as_str() {
+                Some(payload) => payload.to_owned(),
+                None => unix_millis().to_string(),
+            };
+            let qos = get_qos(&step["qos"], QoS::ExactlyOnce);
+            let topic = topic.replace("{id}", &self.id);
+
+            let acked = self.await_ack(AckedRequest::Publish);
+            let sent = self.client.publish(topic, qos, false, payload).await;
+
+            sent.is_ok() && matches!(tokio::time::timeout(timeout, acked).await, Ok(Ok(())))
+        } else if let Some(filter) = step["await"].as_str() {
+            let filter = filter.replace("{id}", &self.id);
+            let received =
+                tokio::time::timeout(timeout, self.poller.receive(&filter, self.since)).await;
+
+            matches!(received, Ok(Some(_)))
+        } else if let Some(filter) = step["subscribe"].as_str() {
+            let filter = filter.replace("{id}", &self.id);
+            let qos = get_qos(&step["qos"], QoS::AtLeastOnce);
+
+            let _ = self
+                .poller
+                .subscriptions
+                .send(SubscriptionChange::Subscribe(filter.clone()));
+
+            let acked = self.await_ack(AckedRequest::Subscribe);
+            let sent = self.client.subscribe(filter, qos).await;
+
+            sent.is_ok() && matches!(tokio::time::timeout(timeout, acked).await, Ok(Ok(())))
+        } else if let Some(filter) = step["unsubscribe"].as_str() {
+            let filter = filter.replace("{id}", &self.id);
+
+            let _ = self
+                .poller
+                .subscriptions
+                .send(SubscriptionChange::Unsubscribe(filter.clone()));
+
+            let acked = self.await_ack(AckedRequest::Unsubscribe);
+            let sent = self.client.unsubscribe(filter).await;
+
+            sent.is_ok() && matches!(tokio::time::timeout(timeout, acked).await, Ok(Ok(())))
+        } else {
+            panic!("Unknown mqtt step: {:?}", step);
+        }
+    }
+
+    /// Tells the poller a request is about to be sent, before it is, so it
+    /// can't go out unnoticed.
+    fn await_ack(&self, request: AckedRequest) -> oneshot::Receiver<()> {
+        let (done, acked) = oneshot::channel();
+        let _ = self.poller.acks.send(AwaitedAck { request, done });
+
+        acked
+    }
+}
+
+#[async_trait]
+impl RunStep for StepRunner<'_> {
+    async fn run_step(&mut self, index: usize, step: &Value) -> Option<Instant> {
+        let start_time = Instant::now();
+        if index == 0 {
+            self.since = start_time;
+        }
+
+        self.run(step).await.then_some(start_time)
+    }
+}
+
+/// Matches a topic against a subscription filter. Shared subscriptions
+/// (`$share/group/filter`) match on their filter part.
+fn filter_matches(filter: &str, topic: &str) -> bool {
+    let filter = match filter.strip_prefix("$share/") {
+        Some(shared) => shared.split_once('/').map_or(shared, |(_, filter)| filter),
+        None => filter,
+    };
+
+    rumqttc::matches(topic, filter)
+}
+
+fn sent_millis(payload: &[u8]) -> Option<u128> {
+    std::str::from_utf8(payload).ok()?.parse().ok()
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// Builds the broker options for the transport selected by the scenario's
+/// `transport` key: `tcp` (default), `tls`, `ws` or `wss`.
+fn create_mqtt_options(
+    id: String,
+    host: &str,
+    port: u16,
+    scenario: &Value,
+) -> Result<MqttOptions, String> {
+    let transport = scenario["transport"].as_str().unwrap_or("tcp");
+
+    let mut mqtt_options = match transport {
+        "tcp" | "tls" => MqttOptions::new(id, host, port),
+        // Websocket transports connect to the full url instead of host and port
+        "ws" | "wss" => {
+            let path = scenario["path"].as_str().unwrap_or("/mqtt");
+            MqttOptions::new(id, format!("{transport}://{host}:{port}{path}"), port)
+        }
+        _ => return Err(format!("unknown transport {transport}")),
+    };
+
+    let transport = match transport {
+        "tls" => {
+            Transport::tls_with_config(tls_config(&scenario["tls"])?)
+        }
+        "ws" => Transport::ws(),
+        "wss" => {
+            Transport::wss_with_config(tls_config(&scenario["tls"])?)
+        }
+        _ => Transport::tcp(),
+    };
+
+    mqtt_options.set_transport(transport);
+
+    Ok(mqtt_options)
+}
+
+/// Applies the session keys of the scenario. Anything left out keeps the
+/// rumqttc defaults, values rumqttc would reject are an error.
+fn set_session_options(
+    mqtt_options: &mut MqttOptions,
+    id: usize,
+    scenario: &Value,
+) -> Result<(), String> {
+    if let Some(keep_alive) = scenario["keep-alive"].as_str() {
+        let keep_alive = utils::time::string_to_millis_u128(keep_alive) as u64;
+        if keep_alive < 5000 {
+            return Err(format!(
+                "keep-alive must be at least 5s, got {keep_alive}ms"
+            ));
+        }
+
+        mqtt_options.set_keep_alive(Duration::from_millis(keep_alive));
+    }
+
+    if let Some(clean_session) = scenario["clean-session"].as_bool() {
+        mqtt_options.set_clean_session(clean_session);
+    }
+
+    if let Some(max_inflight) = scenario["max-inflight"].as_u64() {
+        let max_inflight = u16::try_from(max_inflight)
+            .ok()
+            .filter(|max_inflight| *max_inflight > 0)
+            .ok_or_else(|| format!("max-inflight must be 1 to 65535, got {max_inflight}"))?;
+
+        mqtt_options.set_inflight(max_inflight);
+    }
+
+    let last_will = &scenario["last-will"];
+    if let Some(topic) = last_will["topic"].as_str() {
+        if last_will["qos"].as_u64().is_some_and(|qos| qos > 2) {
+            return Err(format!("invalid last-will qos in {last_will:?}"));
+        }
+
+        let topic = topic.replace("{id}", &id.to_string());
+        let payload = last_will["payload"].as_str().unwrap_or("").to_owned();
+        let retain = last_will["retain"].as_bool().unwrap_or(false);
+
+        mqtt_options.set_last_will(LastWill::new(
+            topic,
+            payload,
+            get_qos(&last_will["qos"], QoS::AtLeastOnce),
+            retain,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses a `qos` key, falling back to `default` when it is missing.
+fn get_qos(qos: &Value, default: QoS) -> QoS {
+    match qos.as_u64() {
+        Some(0) => QoS::AtMostOnce,
+        Some(1) => QoS::AtLeastOnce,
+        Some(2) => QoS::ExactlyOnce,
+        Some(qos) => panic!("Invalid qos: {qos}"),
+        None => default,
+    }
+}
+
+/// Builds the TLS setup from the `ca` certificate, or the system's root
+/// certificates without one, and the optional `client-cert`/`client-key` pair.
+fn tls_config(tls: &Value) -> Result<TlsConfiguration, String> {
+    let mut roots = RootCertStore::empty();
+    match tls["ca"].as_str() {
+        Some(ca_path) => {
+            for cert in read_certs(ca_path)? {
+                roots
+                    .add(&cert)
+                    .map_err(|error| format!("invalid certificate in {ca_path}: {error}"))?;
+            }
+        }
+        None => {
+            let certs = rustls_native_certs::load_native_certs()
+                .map_err(|error| format!("cannot load the system's root certificates: {error}"))?;
+            // Certificates of the system rustls can't parse are skipped
+            let certs: Vec<_> = certs.into_iter().map(|cert| cert.0).collect();
+            roots.add_parsable_certificates(&certs);
+
+            if roots.is_empty() {
+                return Err("no tls.ca set and no system root certificates found".to_owned());
+            }
+        }
+    }
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let config = match (tls["client-cert"].as_str(), tls["client-key"].as_str()) {
+        (Some(cert_path), Some(key_path)) => config
+            .with_single_cert(read_certs(cert_path)?, read_key(key_path)?)
+            .map_err(|error| format!("invalid client certificate or key: {error}"))?,
+        (None, None) => config.with_no_client_auth(),
+        _ => return Err("tls.client-cert and tls.client-key must be set together".to_owned()),
+    };
+
+    Ok(config.into())
+}
+
+fn read_certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let pem = read_tls_file(path)?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .map_err(|error| format!("cannot read {path}: {error}"))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificate in {path}"));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Reads the first PKCS#8, RSA (PKCS#1) or EC (SEC1) private key.
+fn read_key(path: &str) -> Result<PrivateKey, String> {
+    let pem = read_tls_file(path)?;
+    let readers = [
+        rustls_pemfile::pkcs8_private_keys,
+        rustls_pemfile::rsa_private_keys,
+        rustls_pemfile::ec_private_keys,
+    ];
+
+    readers
+        .into_iter()
+        .find_map(|read| read(&mut pem.as_slice()).ok()?.into_iter().next())
+        .map(PrivateKey)
+        .ok_or_else(|| format!("no private key in {path}"))
+}
+
+fn read_tls_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|error| format!("cannot read {path}: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_match_with_wildcards() {
+        assert!(filter_matches("sensors/+/temp", "sensors/a/temp"));
+        assert!(!filter_matches("sensors/+/temp", "sensors/a/b/temp"));
+        assert!(filter_matches("sensors/#", "sensors/a/b"));
+        assert!(!filter_matches("sensors/a", "sensors/b"));
+    }
+
+    #[test]
+    fn shared_subscriptions_match_on_their_filter() {
+        assert!(filter_matches("$share/workers/jobs/+", "jobs/1"));
+        assert!(filter_matches("$share/workers/jobs/#", "jobs/a/b"));
+        assert!(!filter_matches("$share/workers/jobs/+", "workers/jobs"));
+    }
+
+    fn storm(yaml: &str, id: usize) -> Option<ReconnectStorm> {
+        let scenario = serde_yaml::from_str(&format!("reconnect-storm: {yaml}")).unwrap();
+        ReconnectStorm::from_scenario(&scenario, id)
+    }
+
+    #[test]
+    fn storms_take_only_the_first_clients_offline() {
+        let yaml = "{clients: 2, at: 1s, offline: 2s, repeat: 10s, mode: drop}";
+        let storm = storm(yaml, 1).unwrap();
+
+        assert_eq!(storm.at, Duration::from_secs(1));
+        assert_eq!(storm.repeat, Some(Duration::from_secs(10)));
+        assert!(!storm.graceful);
+        assert!(self::storm(yaml, 2).is_none());
+        assert!(ReconnectStorm::from_scenario(&Value::Null, 0).is_none());
+    }
+
+    #[test]
+    fn storm_reconnects_are_spread() {
+        let storm = storm("{clients: 4, offline: 1s, spread: 400ms}", 0).unwrap();
+
+        assert!(storm.graceful);
+        assert_eq!(storm.repeat, None);
+        let offline: Vec<_> = (0..4).map(|id| storm.offline_time(id).as_millis()).collect();
+        assert_eq!(offline, [1000, 1100, 1200, 1300]);
+    }
+
+    #[tokio::test]
+    async fn going_offline_keeps_the_pending_requests() {
+        // Nothing listens on the port, the disconnect gives up at once
+        let mqtt_options = MqttOptions::new("client", "127.0.0.1", 1);
+        let (_client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
+        let publish = Request::Publish(rumqttc::Publish::new("topic", QoS::AtLeastOnce, "1"));
+        eventloop.pending = vec![publish.clone()].into_iter();
+
+        let started = Instant::now();
+        go_offline(&mut eventloop, true, 10).await;
+
+        assert!(started.elapsed() <= DISCONNECT_TIMEOUT);
+        assert_eq!(eventloop.pending.collect::<Vec<_>>(), [publish]);
+    }
+
+    /// Options with the session keys of `yaml` applied.
+    fn session_options(yaml: &str) -> Result<MqttOptions, String> {
+        let mut mqtt_options = MqttOptions::new("client", "localhost", 1883);
+        set_session_options(&mut mqtt_options, 7, &serde_yaml::from_str(yaml).unwrap())?;
+        Ok(mqtt_options)
+    }
+
+    #[test]
+    fn session_keys_are_applied() {
+        let mqtt_options = session_options(
+            "{keep-alive: 5s, clean-session: false, max-inflight: 1, \
+             last-will: {topic: 'status/{id}', payload: gone, qos: 0}}",
+        )
+        .unwrap();
+
+        assert_eq!(mqtt_options.keep_alive(), Duration::from_secs(5));
+        assert!(!mqtt_options.clean_session());
+        assert_eq!(mqtt_options.inflight(), 1);
+
+        let last_will = mqtt_options.last_will().unwrap();
+        assert_eq!(last_will.topic, "status/7");
+        assert_eq!(last_will.qos, QoS::AtMostOnce);
+    }
+
+    #[test]
+    fn session_values_rumqttc_rejects_are_errors() {
+        let error = session_options("keep-alive: 4999ms").unwrap_err();
+        assert_eq!(error, "keep-alive must be at least 5s, got 4999ms");
+
+        let error = session_options("max-inflight: 0").unwrap_err();
+        assert_eq!(error, "max-inflight must be 1 to 65535, got 0");
+        assert!(session_options("max-inflight: 65536").is_err());
+
+        assert!(session_options("last-will: {topic: status, qos: 3}").is_err());
+    }
+
+    fn cert(file: &str) -> String {
+        format!("{}/tests/certs/{file}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    /// Builds the TLS setup of a `tls` section naming the test certificates.
+    fn tls(yaml: &str) -> Result<(), String> {
+        let yaml = yaml
+            .replace("cert.pem", &cert("cert.pem"))
+            .replace("key.pem", &cert("key.pem"));
+        tls_config(&serde_yaml::from_str(&yaml).unwrap()).map(drop)
+    }
+
+    #[test]
+    fn tls_trusts_the_ca_and_presents_the_client_cert() {
+        assert!(tls("ca: cert.pem").is_ok());
+        assert!(tls("{ca: cert.pem, client-cert: cert.pem, client-key: key.pem}").is_ok());
+    }
+
+    #[test]
+    fn tls_without_a_ca_trusts_the_system_roots() {
+        assert!(tls("{}").is_ok());
+
+        let scenario = serde_yaml::from_str("transport: wss").unwrap();
+        assert!(create_mqtt_options("client".to_owned(), "localhost", 443, &scenario).is_ok());
+    }
+
+    #[test]
+    fn tls_rejects_unusable_files() {
+        let error = tls("{ca: cert.pem, client-cert: cert.pem}").unwrap_err();
+        assert_eq!(error, "tls.client-cert and tls.client-key must be set together");
+
+        let error = tls("ca: key.pem").unwrap_err();
+        assert!(error.starts_with("no certificate in"), "{error}");
+
+        let error =
+            tls("{ca: cert.pem, client-cert: cert.pem, client-key: cert.pem}").unwrap_err();
+        assert!(error.starts_with("no private key in"), "{error}");
+
+        let error = tls("ca: missing.pem").unwrap_err();
+        assert!(error.starts_with("cannot read missing.pem"), "{error}");
+    }
+}
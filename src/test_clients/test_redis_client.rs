@@ -0,0 +1,334 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use rand::Rng;
+use serde_yaml::Value;
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    clients::redis_client::{RedisClient, Reply},
+    utils,
+};
+
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, Phase, Phases, RunStep, Step, TestClient, TestClientData},
+};
+
+const DEFAULT_TIMEOUT: &str = "10s";
+
+/// Sends commands to a redis server and reports their latency per command.
+pub struct RedisProtocol;
+
+impl Protocol for RedisProtocol {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        if step["command"].is_null() == step["commands"].is_null() {
+            return Err(format!("expected one of command or commands in {step:?}"));
+        }
+
+        if !step["pipeline"].is_null() && step["pipeline"].as_u64().is_none_or(|n| n == 0) {
+            return Err(format!("pipeline must be a positive number in {step:?}"));
+        }
+
+        commands(step)?;
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &[
+            "command {name}",
+            "error {kind}",
+            "nil",
+            "connect",
+            "connection-error",
+            "timeout",
+        ]
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        Arc::new(TestRedisClient::new(id, host, port, scenario_map, steps, stop))
+    }
+}
+
+pub struct TestRedisClient {
+    client: Arc<Mutex<RedisClient>>,
+    /// The client's steps with `{id}` filled in
+    pipelines: Arc<Phases<Pipeline>>,
+    client_data: Arc<Mutex<TestClientData>>,
+}
+
+/// Runs the pipelines of a phase of a client.
+struct PipelineRunner<'a> {
+    client: &'a mut RedisClient,
+    pipelines: &'a [Pipeline],
+    metrics: Metrics,
+    /// Still set after the loop when a pipeline was interrupted
+    in_flight: bool,
+}
+
+#[async_trait]
+impl RunStep for PipelineRunner<'_> {
+    async fn run_step(&mut self, index: usize, _step: &Value) -> Option<Instant> {
+        self.in_flight = true;
+        let started = self.pipelines[index]
+            .run(self.client, &mut self.metrics)
+            .await;
+        self.in_flight = false;
+
+        started
+    }
+}
+
+/// Commands sent together, each repeated `repeat` times.
+struct Pipeline {
+    commands: Vec<Vec<String>>,
+    repeat: usize,
+    timeout: Duration,
+}
+
+impl TestRedisClient {
+    pub fn new(
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        let client = Arc::new(Mutex::new(RedisClient::new(&format!("{host}:{port}"))));
+
+        let pipelines = Arc::new(Phases::new(&scenario_map, &steps, |step| {
+            Pipeline::new(step, id)
+        }));
+
+        let interval = utils::file::get_interval(&scenario_map);
+
+        let client_data = Arc::new(Mutex::new(TestClientData::new(
+            scenario_map,
+            steps,
+            stop,
+            interval,
+            id,
+        )));
+
+        Self {
+            client,
+            pipelines,
+            client_data,
+        }
+    }
+
+    /// Runs the pipelines of `phase` over the client's connection.
+    fn run(&self, phase: Phase) -> JoinHandle<()> {
+        let client_data = self.client_data.clone();
+        let client = self.client.clone();
+        let pipelines = self.pipelines.clone();
+
+        tokio::spawn(async move {
+            let mut client_data = client_data.lock().await;
+            let mut client = client.lock().await;
+            let mut runner = PipelineRunner {
+                client: &mut client,
+                pipelines: pipelines.get(phase),
+                metrics: Metrics::default(),
+                in_flight: false,
+            };
+
+            client_data.run_phase(phase, &mut runner).await;
+            // The replies to an interrupted pipeline would be taken for the
+            // posttest's
+            if runner.in_flight {
+                runner.client.disconnect();
+            }
+            client_data.metrics.merge(runner.metrics);
+        })
+    }
+}
+
+impl TestClient for TestRedisClient {
+    fn pretest(&self) -> JoinHandle<()> {
+        self.run(Phase::Pretest)
+    }
+
+    fn test_loop(&self) -> JoinHandle<()> {
+        self.run(Phase::TestLoop)
+    }
+
+    fn posttest(&self) -> JoinHandle<()> {
+        self.run(Phase::Posttest)
+    }
+
+    fn teardown(&self) -> JoinHandle<()> {
+        let client = self.client.clone();
+
+        tokio::spawn(async move {
+            client.lock().await.disconnect();
+        })
+    }
+
+    fn client_data(&self) -> Arc<Mutex<TestClientData>> {
+        self.client_data.clone()
+    }
+}
+
+impl Pipeline {
+    fn new(step: &Value, id: usize) -> Self {
+        let timeout = step["timeout"].as_str().unwrap_or(DEFAULT_TIMEOUT);
+        let id = id.to_string();
+
+        Self {
+            commands: commands(step)
+                .unwrap()
+                .into_iter()
+                .map(|command| {
+                    command
+                        .into_iter()
+                        .map(|argument| argument.replace("{id}", &id))
+                        .collect()
+                })
+                .collect(),
+            repeat: step["pipeline"].as_u64().unwrap_or(1) as usize,
+            timeout: Duration::from_millis(utils::time::string_to_millis_u128(timeout) as u64),
+        }
+    }
+
+    /// Sends the commands and waits for all replies. Returns when the step's
+    /// latency is measured from, after connecting, or `None` when it failed
+    /// or got an error reply.
+    async fn run(&self, client: &mut RedisClient, metrics: &mut Metrics) -> Option<Instant> {
+        if !client.is_connected() {
+            let start_time = Instant::now();
+
+            match client.connect().await {
+                Ok(()) => metrics.record("connect", start_time.elapsed().as_millis()),
+                Err(_) => {
+                    metrics.record("connection-error", 0);
+                    return None;
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        let mut commands = Vec::new();
+        for n in 0..self.repeat {
+            for command in &self.commands {
+                names.push(format!("command {}", command[0].to_uppercase()));
+                commands.push(
+                    command
+                        .iter()
+                        .map(|argument| fill(argument, n).into_bytes())
+                        .collect(),
+                );
+            }
+        }
+
+        let start_time = Instant::now();
+
+        if client.send(&commands).await.is_err() {
+            metrics.record("connection-error", 0);
+            client.disconnect();
+            return None;
+        }
+
+        // Every command of the pipeline was written at once, so each one's
+        // latency runs from then until its reply
+        let mut failed = false;
+
+        for name in names {
+            match tokio::time::timeout(self.timeout, client.receive()).await {
+                Ok(Ok(reply)) => {
+                    metrics.record_latency(&name, start_time.elapsed());
+
+                    match reply {
+                        Reply::Value => {}
+                        Reply::Nil => metrics.record("nil", 0),
+                        Reply::Error(message) => {
+                            let kind = message.split_whitespace().next().unwrap_or("ERR");
+                            metrics.record(&format!("error {kind}"), 0);
+                            failed = true;
+                        }
+                    }
+                }
+                Ok(Err(_)) => {
+                    metrics.record("connection-error", 0);
+                    client.disconnect();
+                    return None;
+                }
+                Err(_) => {
+                    // Replies still on their way would be taken for the next step's
+                    metrics.record("timeout", 0);
+                    client.disconnect();
+                    return None;
+                }
+            }
+        }
+
+        (!failed).then_some(start_time)
+    }
+}
+
+/// A step's commands, each a list of arguments. A command is either a string
+/// split on whitespace or a list.
+fn commands(step: &Value) -> Result<Vec<Vec<String>>, String> {
+    let command = |value: &Value| -> Result<Vec<String>, String> {
+        let arguments: Vec<String> = match value {
+            Value::String(command) => command.split_whitespace().map(str::to_owned).collect(),
+            Value::Sequence(arguments) => arguments
+                .iter()
+                .map(|argument| match argument {
+                    Value::String(argument) => Ok(argument.clone()),
+                    Value::Number(number) => Ok(number.to_string()),
+                    _ => Err(format!("expected a string or number argument in {value:?}")),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(format!("expected a command string or list in {value:?}")),
+        };
+
+        if arguments.is_empty() {
+            return Err(format!("empty command in {step:?}"));
+        }
+
+        Ok(arguments)
+    };
+
+    match &step["commands"] {
+        Value::Null => Ok(vec![command(&step["command"])?]),
+        Value::Sequence(commands) => commands.iter().map(command).collect(),
+        commands => Err(format!("expected a list of commands in {commands:?}")),
+    }
+}
+
+/// Replaces `{n}` with the command's position in the pipeline and
+/// `{random:N}` with a number below N.
+fn fill(argument: &str, n: usize) -> String {
+    let mut argument = argument.replace("{n}", &n.to_string());
+
+    while let Some(start) = argument.find("{random:") {
+        let Some(end) = argument[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        let below = argument[start + 8..end].parse::<u64>().unwrap_or(u64::MAX).max(1);
+        let random = rand::thread_rng().gen_range(0..below);
+        argument.replace_range(start..=end, &random.to_string());
+    }
+
+    argument
+}
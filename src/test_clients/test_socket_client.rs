@@ -0,0 +1,316 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use regex::bytes::Regex;
+use serde_yaml::Value;
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    clients::socket_client::{Expect, SocketClient, Transport},
+    utils::{self, encoding},
+};
+
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, Phase, Phases, RunStep, Step, TestClient, TestClientData},
+};
+
+const DEFAULT_EXPECT_TIMEOUT: &str = "10s";
+const SEND_KINDS: [&str; 4] = ["send", "send-hex", "send-base64", "send-file"];
+
+/// Sends bytes over a raw `tcp` or `udp` socket and waits for a response
+/// found by its length, a delimiter or a regex.
+pub struct SocketProtocol {
+    transport: Transport,
+}
+
+impl SocketProtocol {
+    pub fn new(transport: Transport) -> Self {
+        Self { transport }
+    }
+}
+
+impl Protocol for SocketProtocol {
+    fn name(&self) -> &'static str {
+        match self.transport {
+            Transport::Tcp => "tcp",
+            Transport::Udp => "udp",
+        }
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        let sends = SEND_KINDS
+            .iter()
+            .filter(|kind| !step[**kind].is_null())
+            .count();
+
+        if sends > 1 || (sends == 0 && step["expect"].is_null()) {
+            return Err(format!(
+                "expected one of send, send-hex, send-base64 or send-file, and or expect in {step:?}"
+            ));
+        }
+
+        payload(step, 0)?;
+        expect(&step["expect"])?;
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &[
+            "connect",
+            "connection-error",
+            "timeout",
+            "bytes-sent",
+            "bytes-received",
+        ]
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        Arc::new(TestSocketClient::new(
+            self.transport,
+            id,
+            host,
+            port,
+            scenario_map,
+            steps,
+            stop,
+        ))
+    }
+}
+
+pub struct TestSocketClient {
+    client: Arc<Mutex<SocketClient>>,
+    /// The client's steps with their payloads built
+    exchanges: Arc<Phases<Exchange>>,
+    client_data: Arc<Mutex<TestClientData>>,
+}
+
+/// Runs the exchanges of a phase of a client.
+struct ExchangeRunner<'a> {
+    client: &'a mut SocketClient,
+    exchanges: &'a [Exchange],
+    metrics: Metrics,
+    /// Still set after the loop when an exchange was interrupted
+    in_flight: bool,
+}
+
+#[async_trait]
+impl RunStep for ExchangeRunner<'_> {
+    async fn run_step(&mut self, index: usize, _step: &Value) -> Option<Instant> {
+        self.in_flight = true;
+        let started = self.exchanges[index]
+            .run(self.client, &mut self.metrics)
+            .await;
+        self.in_flight = false;
+
+        started
+    }
+}
+
+struct Exchange {
+    payload: Option<Vec<u8>>,
+    expect: Option<Expect>,
+    timeout: Duration,
+}
+
+impl TestSocketClient {
+    pub fn new(
+        transport: Transport,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        let client = Arc::new(Mutex::new(SocketClient::new(
+            transport,
+            &format!("{host}:{port}"),
+        )));
+
+        let exchanges = Arc::new(Phases::new(&scenario_map, &steps, |step| {
+            Exchange::new(step, id)
+        }));
+
+        let interval = utils::file::get_interval(&scenario_map);
+
+        let client_data = Arc::new(Mutex::new(TestClientData::new(
+            scenario_map,
+            steps,
+            stop,
+            interval,
+            id,
+        )));
+
+        Self {
+            client,
+            exchanges,
+            client_data,
+        }
+    }
+
+    /// Runs the exchanges of `phase` over the client's connection.
+    fn run(&self, phase: Phase) -> JoinHandle<()> {
+        let client_data = self.client_data.clone();
+        let client = self.client.clone();
+        let exchanges = self.exchanges.clone();
+
+        tokio::spawn(async move {
+            let mut client_data = client_data.lock().await;
+            let mut client = client.lock().await;
+            let mut runner = ExchangeRunner {
+                client: &mut client,
+                exchanges: exchanges.get(phase),
+                metrics: Metrics::default(),
+                in_flight: false,
+            };
+
+            client_data.run_phase(phase, &mut runner).await;
+            // The reply to an interrupted exchange would be taken for the
+            // posttest's
+            if runner.in_flight {
+                runner.client.disconnect();
+            }
+            client_data.metrics.merge(runner.metrics);
+        })
+    }
+}
+
+impl TestClient for TestSocketClient {
+    fn pretest(&self) -> JoinHandle<()> {
+        self.run(Phase::Pretest)
+    }
+
+    fn test_loop(&self) -> JoinHandle<()> {
+        self.run(Phase::TestLoop)
+    }
+
+    fn posttest(&self) -> JoinHandle<()> {
+        self.run(Phase::Posttest)
+    }
+
+    fn teardown(&self) -> JoinHandle<()> {
+        let client = self.client.clone();
+
+        tokio::spawn(async move {
+            client.lock().await.disconnect();
+        })
+    }
+
+    fn client_data(&self) -> Arc<Mutex<TestClientData>> {
+        self.client_data.clone()
+    }
+}
+
+impl Exchange {
+    fn new(step: &Value, id: usize) -> Self {
+        let timeout = step["expect"]["timeout"]
+            .as_str()
+            .unwrap_or(DEFAULT_EXPECT_TIMEOUT);
+
+        Self {
+            payload: payload(step, id).unwrap(),
+            expect: expect(&step["expect"]).unwrap(),
+            timeout: Duration::from_millis(utils::time::string_to_millis_u128(timeout) as u64),
+        }
+    }
+
+    /// Sends the payload and waits for the response. Returns when the step's
+    /// latency is measured from, after connecting, or `None` when it failed.
+    async fn run(&self, client: &mut SocketClient, metrics: &mut Metrics) -> Option<Instant> {
+        if !client.is_connected() {
+            let start_time = Instant::now();
+
+            match client.connect().await {
+                Ok(()) => metrics.record("connect", start_time.elapsed().as_millis()),
+                Err(_) => {
+                    metrics.record("connection-error", 0);
+                    return None;
+                }
+            }
+        }
+
+        let start_time = Instant::now();
+
+        if let Some(payload) = &self.payload {
+            if client.send(payload).await.is_err() {
+                metrics.record("connection-error", 0);
+                client.disconnect();
+                return None;
+            }
+
+            metrics.add("bytes-sent", payload.len());
+        }
+
+        if let Some(expect) = &self.expect {
+            match tokio::time::timeout(self.timeout, client.receive(expect)).await {
+                Ok(Ok(received)) => metrics.add("bytes-received", received),
+                Ok(Err(_)) => {
+                    metrics.record("connection-error", 0);
+                    client.disconnect();
+                    return None;
+                }
+                Err(_) => {
+                    // A late response would be taken for the next step's
+                    metrics.record("timeout", 0);
+                    client.disconnect();
+                    return None;
+                }
+            }
+        }
+
+        Some(start_time)
+    }
+}
+
+/// The bytes a step sends, `{id}` in text replaced by the client's number.
+fn payload(step: &Value, id: usize) -> Result<Option<Vec<u8>>, String> {
+    if let Some(text) = step["send"].as_str() {
+        Ok(Some(text.replace("{id}", &id.to_string()).into_bytes()))
+    } else if let Some(hex) = step["send-hex"].as_str() {
+        encoding::decode_hex(hex)
+            .map(Some)
+            .ok_or_else(|| format!("invalid hex in {step:?}"))
+    } else if let Some(base64) = step["send-base64"].as_str() {
+        encoding::decode_base64(base64)
+            .map(Some)
+            .ok_or_else(|| format!("invalid base64 in {step:?}"))
+    } else if let Some(path) = step["send-file"].as_str() {
+        std::fs::read(path)
+            .map(Some)
+            .map_err(|error| format!("can't read {path}: {error}"))
+    } else {
+        Ok(None)
+    }
+}
+
+fn expect(expect: &Value) -> Result<Option<Expect>, String> {
+    if expect.is_null() {
+        return Ok(None);
+    }
+
+    if let Some(length) = expect["length"].as_u64() {
+        Ok(Some(Expect::Length(length as usize)))
+    } else if let Some(delimiter) = expect["delimiter"].as_str().filter(|d| !d.is_empty()) {
+        Ok(Some(Expect::Delimiter(delimiter.as_bytes().to_vec())))
+    } else if let Some(regex) = expect["regex"].as_str() {
+        Regex::new(regex)
+            .map(|regex| Some(Expect::Regex(regex)))
+            .map_err(|error| error.to_string())
+    } else {
+        Err(format!("expected one of length, delimiter or regex in {expect:?}"))
+    }
+}
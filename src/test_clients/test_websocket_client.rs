@@ -0,0 +1,394 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use regex::Regex;
+use serde_yaml::Value;
+use tokio::{
+    net::TcpStream,
+    sync::{
+        mpsc::{self, UnboundedReceiver},
+        watch, Mutex,
+    },
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::utils;
+
+use super::{
+    registry::Protocol,
+    test_client::{Metrics, Phase, RunStep, Step, TestClient, TestClientData},
+};
+
+/// Time an await step waits for its message, and a ping for the pong.
+const DEFAULT_STEP_TIMEOUT: &str = "10s";
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Sends text and binary frames, awaits messages matching a pattern, pings
+/// and closes over one connection per client.
+pub struct WebsocketProtocol;
+
+impl Protocol for WebsocketProtocol {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    fn parse_step(&self, step: &Value) -> Result<Step, String> {
+        let kinds = ["send", "send-binary", "await", "ping", "close"]
+            .iter()
+            .filter(|kind| !step[**kind].is_null())
+            .count();
+
+        if kinds != 1 {
+            return Err(format!(
+                "expected one of send, send-binary, await, ping or close in {step:?}"
+            ));
+        }
+
+        if let Some(pattern) = step["await"].as_str() {
+            // {id} is filled in before the pattern is compiled
+            Regex::new(&pattern.replace("{id}", "0")).map_err(|error| error.to_string())?;
+        }
+
+        if let Some(hex) = step["send-binary"].as_str() {
+            utils::encoding::decode_hex(hex).ok_or_else(|| format!("invalid hex in {step:?}"))?;
+        }
+
+        Ok(Step::new(step.clone()))
+    }
+
+    fn metrics(&self) -> &'static [&'static str] {
+        &["connect", "connection-error", "message-sent", "message-received"]
+    }
+
+    fn create_client(
+        &self,
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Arc<dyn TestClient> {
+        Arc::new(TestWebsocketClient::new(id, host, port, scenario_map, steps, stop))
+    }
+}
+
+pub struct TestWebsocketClient {
+    url: Arc<String>,
+    connection: Arc<Mutex<Option<Connection>>>,
+    client_data: Arc<Mutex<TestClientData>>,
+}
+
+/// An open socket. Incoming messages are read by a task of their own, which
+/// also answers the server's pings.
+struct Connection {
+    sink: SplitSink<Socket, Message>,
+    incoming: UnboundedReceiver<Message>,
+    /// Messages that arrived while waiting for a pong, kept for awaits
+    pending: VecDeque<Message>,
+    reader: JoinHandle<()>,
+    last_sent: Option<Instant>,
+}
+
+struct StepRunner<'a> {
+    url: &'a str,
+    connection: &'a mut Option<Connection>,
+    patterns: HashMap<String, Regex>,
+    metrics: Metrics,
+    id: String,
+}
+
+impl TestWebsocketClient {
+    pub fn new(
+        id: usize,
+        host: &str,
+        port: u16,
+        scenario_map: Value,
+        steps: Vec<Step>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        let scenario = &scenario_map["scenario"];
+        let transport = scenario["transport"].as_str().unwrap_or("ws");
+        if transport != "ws" && transport != "wss" {
+            panic!("Unknown websocket transport: {transport}");
+        }
+
+        let path = scenario["path"]
+            .as_str()
+            .unwrap_or("/")
+            .replace("{id}", &id.to_string());
+        let url = Arc::new(format!("{transport}://{host}:{port}{path}"));
+
+        let interval = utils::file::get_interval(&scenario_map);
+
+        let client_data = Arc::new(Mutex::new(TestClientData::new(
+            scenario_map,
+            steps,
+            stop,
+            interval,
+            id,
+        )));
+
+        Self {
+            url,
+            connection: Arc::new(Mutex::new(None)),
+            client_data,
+        }
+    }
+
+    /// Runs the steps of `phase` over the client's connection.
+    fn run(&self, phase: Phase) -> JoinHandle<()> {
+        let client_data = self.client_data.clone();
+        let connection = self.connection.clone();
+        let url = self.url.clone();
+
+        tokio::spawn(async move {
+            let mut client_data = client_data.lock().await;
+            let mut connection = connection.lock().await;
+            let mut runner = StepRunner::new(&url, &mut connection, client_data.id());
+
+            client_data.run_phase(phase, &mut runner).await;
+            client_data.metrics.merge(runner.metrics);
+        })
+    }
+}
+
+impl TestClient for TestWebsocketClient {
+    fn pretest(&self) -> JoinHandle<()> {
+        self.run(Phase::Pretest)
+    }
+
+    fn test_loop(&self) -> JoinHandle<()> {
+        self.run(Phase::TestLoop)
+    }
+
+    fn posttest(&self) -> JoinHandle<()> {
+        self.run(Phase::Posttest)
+    }
+
+    /// Closes a connection left open.
+    fn teardown(&self) -> JoinHandle<()> {
+        let connection = self.connection.clone();
+
+        tokio::spawn(async move {
+            if let Some(connection) = connection.lock().await.take() {
+                connection.close().await;
+            }
+        })
+    }
+
+    fn client_data(&self) -> Arc<Mutex<TestClientData>> {
+        self.client_data.clone()
+    }
+}
+
+impl Connection {
+    async fn open(url: &str) -> Result<Self, tokio_tungstenite::tungstenite::Error> {
+        let (socket, _) = tokio_tungstenite::connect_async(url).await?;
+        let (sink, stream) = socket.split();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+
+        let reader = tokio::spawn(read(stream, incoming_tx));
+
+        Ok(Self {
+            sink,
+            incoming,
+            pending: VecDeque::new(),
+            reader,
+            last_sent: None,
+        })
+    }
+
+    async fn send(&mut self, message: Message) -> bool {
+        let sent = self.sink.send(message).await.is_ok();
+        self.last_sent = Some(Instant::now());
+        sent
+    }
+
+    async fn next_message(&mut self) -> Option<Message> {
+        match self.pending.pop_front() {
+            Some(message) => Some(message),
+            None => self.incoming.recv().await,
+        }
+    }
+
+    /// Sends a close frame and waits a moment for the server's answer.
+    async fn close(mut self) {
+        let _ = self.sink.send(Message::Close(None)).await;
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, &mut self.reader).await;
+        self.reader.abort();
+    }
+}
+
+/// Forwards incoming messages until the socket closes. Pongs to the server's
+/// pings go out as part of reading.
+async fn read(mut stream: SplitStream<Socket>, incoming: mpsc::UnboundedSender<Message>) {
+    while let Some(Ok(message)) = stream.next().await {
+        if let Message::Close(_) = message {
+            break;
+        }
+
+        if incoming.send(message).is_err() {
+            break;
+        }
+    }
+}
+
+impl<'a> StepRunner<'a> {
+    fn new(url: &'a str, connection: &'a mut Option<Connection>, id: usize) -> Self {
+        Self {
+            url,
+            connection,
+            patterns: HashMap::new(),
+            metrics: Metrics::default(),
+            id: id.to_string(),
+        }
+    }
+
+    /// Opens the connection unless it's open already.
+    async fn connect(&mut self) -> Option<&mut Connection> {
+        if self.connection.is_none() {
+            let start_time = Instant::now();
+
+            match Connection::open(self.url).await {
+                Ok(connection) => {
+                    self.metrics
+                        .record_latency("connect", start_time.elapsed());
+                    *self.connection = Some(connection);
+                }
+                Err(_) => {
+                    self.metrics.record("connection-error", 0);
+                    return None;
+                }
+            }
+        }
+
+        self.connection.as_mut()
+    }
+
+    /// Runs a single step. Returns `None` when it failed or timed out and
+    /// shouldn't be counted, otherwise when its latency is measured from if
+    /// not the step's start: an await measures the round trip from the last
+    /// message sent.
+    async fn run(&mut self, step: &Value) -> Option<Option<Instant>> {
+        let timeout = step["timeout"].as_str().unwrap_or(DEFAULT_STEP_TIMEOUT);
+        let timeout = Duration::from_millis(utils::time::string_to_millis_u128(timeout) as u64);
+
+        if let Some(text) = step["send"].as_str() {
+            let text = text.replace("{id}", &self.id);
+            self.send(Message::Text(text)).await
+        } else if let Some(hex) = step["send-binary"].as_str() {
+            self.send(Message::Binary(utils::encoding::decode_hex(hex).unwrap())).await
+        } else if let Some(pattern) = step["await"].as_str() {
+            let pattern = pattern.replace("{id}", &self.id);
+
+            let pattern = self
+                .patterns
+                .entry(pattern.clone())
+                .or_insert_with(|| Regex::new(&pattern).unwrap())
+                .clone();
+
+            self.connect().await?;
+            let connection = self.connection.as_mut().unwrap();
+            let last_sent = connection.last_sent;
+            let received =
+                tokio::time::timeout(timeout, receive(connection, &pattern, &mut self.metrics))
+                    .await;
+
+            match received {
+                Ok(true) => Some(last_sent),
+                Ok(false) => {
+                    // The socket closed, the next step reconnects
+                    self.metrics.record("connection-error", 0);
+                    *self.connection = None;
+                    None
+                }
+                Err(_) => None,
+            }
+        } else if !step["ping"].is_null() {
+            let payload = step["ping"].as_str().unwrap_or_default().as_bytes().to_vec();
+            self.connect().await?;
+            let connection = self.connection.as_mut().unwrap();
+
+            if !connection.send(Message::Ping(payload)).await {
+                return None;
+            }
+
+            // Other messages arriving meanwhile are left for the next await
+            let pong = async {
+                loop {
+                    match connection.incoming.recv().await? {
+                        Message::Pong(_) => return Some(None),
+                        message => connection.pending.push_back(message),
+                    }
+                }
+            };
+
+            tokio::time::timeout(timeout, pong).await.ok()?
+        } else if !step["close"].is_null() {
+            let connection = self.connection.take()?;
+            connection.close().await;
+            Some(None)
+        } else {
+            panic!("Unknown websocket step: {:?}", step);
+        }
+    }
+
+    /// Sends a message, its latency leaves out connecting.
+    async fn send(&mut self, message: Message) -> Option<Option<Instant>> {
+        let connection = self.connect().await?;
+        let start_time = Instant::now();
+
+        if connection.send(message).await {
+            self.metrics.record("message-sent", 0);
+            Some(Some(start_time))
+        } else {
+            self.metrics.record("connection-error", 0);
+            *self.connection = None;
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl RunStep for StepRunner<'_> {
+    async fn run_step(&mut self, _index: usize, step: &Value) -> Option<Instant> {
+        let start_time = Instant::now();
+
+        self.run(step)
+            .await
+            .map(|started| started.unwrap_or(start_time))
+    }
+}
+
+/// Reads messages until one matches, false once the socket is closed.
+/// Binary messages are matched as text.
+async fn receive(connection: &mut Connection, pattern: &Regex, metrics: &mut Metrics) -> bool {
+    while let Some(message) = connection.next_message().await {
+        let text = match &message {
+            Message::Text(text) => text.clone(),
+            Message::Binary(data) => String::from_utf8_lossy(data).into_owned(),
+            _ => continue,
+        };
+
+        metrics.record("message-received", 0);
+
+        if pattern.is_match(&text) {
+            return true;
+        }
+    }
+
+    false
+}
@@ -2,8 +2,6 @@ use std::error::Error;
 
 use serde_yaml::Value;
 
-use crate::test_clients::test_client::Step;
-
 use super::time;
 
 pub fn load_yaml(path: &str) -> Result<Value, Box<dyn Error>> {
@@ -17,17 +15,39 @@ pub fn load_yaml(path: &str) -> Result<Value, Box<dyn Error>> {
 pub fn get_interval(scenario_map: &Value) -> u64 {
     let interval = scenario_map["scenario"]["testloop"]["interval"]
         .as_str()
-        .or(Some("0ms"))
-        .unwrap();
+        .unwrap_or("0ms");
 
     time::string_to_millis_u128(interval) as u64
 }
 
-pub fn get_steps(scenario_map: &mut Value) -> Vec<Step> {
-    scenario_map["scenario"]["testloop"]["steps"]
-        .as_sequence_mut()
-        .unwrap()
-        .iter_mut()
-        .map(|step| Step::new(step.get("step").unwrap().to_owned()))
-        .collect()
+/// Fixed wall-clock time per iteration, 0 when the loop isn't paced.
+pub fn get_pacing(scenario_map: &Value) -> u64 {
+    let pacing = scenario_map["scenario"]["testloop"]["pacing"]
+        .as_str()
+        .unwrap_or("0ms");
+
+    time::string_to_millis_u128(pacing) as u64
+}
+
+/// Number of iterations each client runs before it stops.
+pub fn get_iterations(scenario_map: &Value) -> Option<usize> {
+    scenario_map["scenario"]["testloop"]["iterations"]
+        .as_u64()
+        .map(|iterations| iterations as usize)
+}
+
+/// Number of iterations all clients together run before they stop.
+pub fn get_total_iterations(scenario_map: &Value) -> Option<usize> {
+    scenario_map["scenario"]["testloop"]["total-iterations"]
+        .as_u64()
+        .map(|iterations| iterations as usize)
+}
+
+/// Time in-flight work gets to finish once a client is stopped.
+pub fn get_grace_period(scenario_map: &Value) -> u64 {
+    let grace_period = scenario_map["scenario"]["grace-period"]
+        .as_str()
+        .unwrap_or("1s");
+
+    time::string_to_millis_u128(grace_period) as u64
 }
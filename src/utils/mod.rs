@@ -1,3 +1,5 @@
+pub mod encoding;
 pub mod print;
 pub mod time;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod signal;
\ No newline at end of file
@@ -0,0 +1,58 @@
+use tokio::{sync::watch, task::JoinHandle};
+
+/// Watch on Ctrl-C and SIGTERM of a run, stopped when dropped so it can't
+/// take signals meant for whatever comes after the run.
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+    task: JoinHandle<()>,
+}
+
+impl Shutdown {
+    /// Completes on the first Ctrl-C or SIGTERM.
+    pub async fn requested(&mut self) {
+        while !*self.requested.borrow() {
+            if self.requested.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Watches for Ctrl-C and SIGTERM so the run can shut down in order. Exiting
+/// on a second one is up to the binary.
+pub fn watch_shutdown() -> Shutdown {
+    let (tx, requested) = watch::channel(false);
+
+    let task = tokio::spawn(async move {
+        shutdown_signal().await;
+        println!("\nShutting down, press Ctrl-C again to exit immediately");
+        let _ = tx.send(true);
+    });
+
+    Shutdown { requested, task }
+}
+
+/// Completes on the next Ctrl-C or SIGTERM.
+#[cfg(unix)]
+pub async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Completes on the next Ctrl-C.
+#[cfg(not(unix))]
+pub async fn shutdown_signal() {
+    tokio::signal::ctrl_c().await.unwrap();
+}
@@ -1,14 +1,21 @@
-use std::time::Duration;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
 
 
 use super::print::print_progress;
 
 pub fn string_to_millis_u128(time: &str) -> u128 {
-    let unit: String = time.chars().filter(|c| !c.is_digit(10)).collect();
+    let unit: String = time.chars().filter(|c| !c.is_ascii_digit()).collect();
     let unit = unit.as_str();
     let time: u128 = time
         .chars()
-        .filter(|c| c.is_digit(10))
+        .filter(|c| c.is_ascii_digit())
         .collect::<String>()
         .parse()
         .unwrap();
@@ -24,22 +31,47 @@ pub fn string_to_millis_u128(time: &str) -> u128 {
     time * factor
 }
 
-pub fn create_timer(duration_millis: u128, tx: tokio::sync::broadcast::Sender<bool>) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
+/// Progress bar task of a run, stopped when dropped so it can't outlive an
+/// aborted run.
+pub struct Timer(JoinHandle<()>);
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx).map(|result| result.unwrap())
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Resolves once the duration has passed, drawing a progress bar meanwhile
+/// when `show_progress` is set.
+pub fn create_timer(duration_millis: u128, show_progress: bool) -> Timer {
+    Timer(tokio::spawn(async move {
         let start_time = tokio::time::Instant::now();
+        let end = start_time + Duration::from_millis(duration_millis as u64);
         let mut interval = tokio::time::interval(Duration::from_millis(1000));
 
         loop {
-            let instant = interval.tick().await;
+            // The last tick lands on the end instead of the next full second
+            let instant = tokio::time::timeout_at(end, interval.tick())
+                .await
+                .unwrap_or(end);
 
             let progress =
                 instant.duration_since(start_time).as_millis() as f32 / duration_millis as f32;
-            print_progress(progress);
+            if show_progress {
+                print_progress(progress);
+            }
 
             if instant.duration_since(start_time).as_millis() >= duration_millis {
-                tx.send(true).unwrap();
                 break;
             }
         }
-    })
+    }))
 }
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{http_mock, scenario, step_total};
+
+#[tokio::test]
+async fn iterations_start_at_the_rate() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+duration: 1s
+protocol: http
+arrival-rate:
+  rate: 100
+  max-clients: 10
+testloop:
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let report = scenario.run().await;
+    let arrivals = report.arrivals.as_ref().unwrap();
+
+    let total = step_total(&report, 0);
+    assert!((95..=101).contains(&total), "{report}");
+    assert_eq!(arrivals.dropped, 0, "{report}");
+}
+
+#[tokio::test]
+async fn the_pool_grows_up_to_the_maximum() {
+    let mock = http_mock("delay: 200ms").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+duration: 1s
+grace-period: 1s
+protocol: http
+arrival-rate:
+  rate: 50
+  pre-allocated: 1
+  max-clients: 4
+testloop:
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let report = scenario.run().await;
+    let arrivals = report.arrivals.as_ref().unwrap();
+
+    // Started one by one as none was free, then iterations are dropped
+    // instead of starting more
+    assert_eq!(arrivals.clients_used, 4, "{report}");
+    assert_eq!(arrivals.clients, 4, "{report}");
+    assert!(arrivals.dropped > 0, "{report}");
+}
+
+#[tokio::test]
+async fn iterations_without_a_free_client_are_dropped() {
+    let mock = http_mock("delay: 200ms").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+duration: 1s
+grace-period: 1s
+protocol: http
+arrival-rate:
+  rate: 50
+  max-clients: 2
+testloop:
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let report = scenario.run().await;
+    let arrivals = report.arrivals.as_ref().unwrap();
+
+    // Two clients busy for 200ms each take 10 of the 50 iterations a second
+    let total = step_total(&report, 0);
+    assert!((8..=12).contains(&total), "{report}");
+    assert!(arrivals.dropped >= 35, "{report}");
+    assert!(total + arrivals.dropped <= 51, "{report}");
+}
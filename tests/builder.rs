@@ -0,0 +1,195 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{http_mock, mqtt_broker};
+use loadtester_v2::{
+    scenario::steps::{
+        http::{Graphql, Sse},
+        mqtt::Publish,
+    },
+    HttpStep, MqttStep, Scenario, ScenarioBuilder, ScenarioError,
+};
+
+fn step(yaml: &str) -> serde_yaml::Value {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[tokio::test]
+async fn built_scenarios_run_like_scenario_files() {
+    let mock = http_mock("{}").await;
+    let scenario = Scenario::builder()
+        .protocol("http")
+        .host("127.0.0.1")
+        .port(mock.local_addr().port())
+        .clients(2)
+        .iterations(3)
+        .interval(Duration::from_millis(10))
+        .step(step("endpoint: /first"))
+        .step(step("endpoint: /second"))
+        .build()
+        .unwrap();
+
+    let report = scenario.run().await;
+
+    assert!(!report.aborted);
+    assert!(report.arrivals.is_none());
+    assert_eq!(report.groups.len(), 1);
+
+    let group = &report.groups[0];
+    assert_eq!(group.name, None);
+    assert_eq!(group.protocol, "http");
+    assert_eq!(group.clients, 2);
+    assert_eq!(group.steps.len(), 2);
+    assert_eq!(group.steps[0].count(), 6);
+    assert_eq!(group.steps[1].count(), 6);
+    assert_eq!(mock.requests("/first"), 6);
+    assert_eq!(mock.requests("/second"), 6);
+}
+
+#[tokio::test]
+async fn built_groups_override_the_scenario() {
+    let mock = http_mock("{}").await;
+    let scenario = Scenario::builder()
+        .protocol("http")
+        .host("127.0.0.1")
+        .port(mock.local_addr().port())
+        .iterations(2)
+        .step(step("endpoint: /browse"))
+        .group("browsing", Scenario::builder().clients(1))
+        .group(
+            "searching",
+            Scenario::builder()
+                .clients(2)
+                .iterations(1)
+                .step(step("endpoint: /search")),
+        )
+        .build()
+        .unwrap();
+
+    let report = scenario.run().await;
+
+    let browsing = report.group(Some("browsing")).unwrap();
+    assert_eq!(browsing.clients, 1);
+    assert_eq!(browsing.steps[0].count(), 2);
+
+    let searching = report.group(Some("searching")).unwrap();
+    assert_eq!(searching.clients, 2);
+    assert_eq!(searching.steps.len(), 1);
+    assert_eq!(searching.steps[0].count(), 2);
+
+    assert_eq!(mock.requests("/browse"), 2);
+    assert_eq!(mock.requests("/search"), 2);
+}
+
+#[tokio::test]
+async fn scenario_files_can_be_adjusted_by_the_builder() {
+    let mock = http_mock("{}").await;
+    let scenario = ScenarioBuilder::from_file("testcase.http")
+        .unwrap()
+        .host("127.0.0.1")
+        .port(mock.local_addr().port())
+        .clients(2)
+        .ramp_up(Duration::ZERO)
+        .iterations(1)
+        .build()
+        .unwrap();
+
+    let report = scenario.run().await;
+
+    // The file's steps, run by the builder's clients
+    assert_eq!(report.groups[0].clients, 2);
+    assert_eq!(mock.requests("/"), 2);
+    assert_eq!(mock.requests("/slow"), 2);
+}
+
+#[test]
+fn invalid_scenarios_are_errors() {
+    let http = || Scenario::builder().protocol("http").host("127.0.0.1").port(80);
+
+    assert!(matches!(
+        ScenarioBuilder::from_file("missing"),
+        Err(ScenarioError::File { path, .. }) if path == "./scenarios/missing.yml"
+    ));
+    assert_eq!(
+        Scenario::builder().clients(1).iterations(1).build().err(),
+        Some(ScenarioError::Missing("protocol"))
+    );
+    assert_eq!(
+        http().protocol("gopher").clients(1).iterations(1).build().err(),
+        Some(ScenarioError::UnknownProtocol("gopher".to_owned()))
+    );
+    assert_eq!(
+        http().clients(1).build().err(),
+        Some(ScenarioError::Missing("duration or iterations"))
+    );
+    assert!(matches!(
+        http().clients(1).iterations(1).step(step("sse: {}")).build(),
+        Err(ScenarioError::Invalid(error)) if error.starts_with("Invalid http step")
+    ));
+}
+
+#[test]
+fn typed_steps_lower_to_the_steps_of_scenario_files() {
+    let sse = HttpStep::from(Sse {
+        endpoint: "/events".to_owned(),
+        events: Some(10),
+        event: Some("update".to_owned()),
+        ..Sse::default()
+    });
+    assert_eq!(
+        serde_yaml::Value::from(sse),
+        step("{endpoint: /events, sse: {events: 10, event: update}}")
+    );
+
+    let graphql = HttpStep::from(Graphql {
+        endpoint: "/graphql".to_owned(),
+        query: "query{user{id}}".to_owned(),
+        variables: vec![("user".to_owned(), "user{id}".to_owned())],
+        ..Graphql::default()
+    });
+    assert_eq!(
+        serde_yaml::Value::from(graphql),
+        step("{endpoint: /graphql, graphql: {query: 'query{user{id}}', variables: {user: 'user{id}'}}}")
+    );
+
+    let publish = MqttStep::from(Publish {
+        topic: "echo/{id}".to_owned(),
+        qos: Some(1),
+        timeout: Some(Duration::from_secs(5)),
+        ..Publish::default()
+    });
+    assert_eq!(
+        serde_yaml::Value::from(publish),
+        step("{publish: 'echo/{id}', qos: 1, timeout: 5000ms}")
+    );
+    assert_eq!(
+        serde_yaml::Value::from(MqttStep::await_message("echo/#")),
+        step("await: echo/#")
+    );
+}
+
+#[tokio::test]
+async fn typed_steps_run_like_their_yaml() {
+    let broker = mqtt_broker().await;
+    let scenario = Scenario::builder()
+        .protocol("mqtt")
+        .host("127.0.0.1")
+        .port(broker.local_addr().port())
+        .clients(2)
+        .iterations(3)
+        .pretest_step(MqttStep::subscribe("echo/{id}/#"))
+        .step(MqttStep::publish("echo/{id}/message"))
+        .step(MqttStep::Await {
+            filter: "echo/{id}/#".to_owned(),
+            timeout: Some(Duration::from_secs(5)),
+        })
+        .build()
+        .unwrap();
+
+    let report = scenario.run().await;
+
+    assert_eq!(report.groups[0].steps[0].count(), 6, "{report}");
+    assert_eq!(report.groups[0].steps[1].count(), 6, "{report}");
+    assert_eq!(broker.publishes(), 6);
+}
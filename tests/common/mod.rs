@@ -0,0 +1,82 @@
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use loadtester_v2::{
+    mock::{
+        http::{HttpMock, HttpMockConfig},
+        mqtt::{MqttBroker, MqttMockConfig},
+    },
+    GroupReport, Report, Scenario,
+};
+use serde_yaml::Value;
+
+fn any_port() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, 0))
+}
+
+/// An HTTP mock on a free port, configured like a `mock.http` section.
+pub async fn http_mock(config: &str) -> HttpMock {
+    let config: Value = serde_yaml::from_str(config).unwrap();
+    HttpMock::start(any_port(), HttpMockConfig::from_value(&config))
+        .await
+        .unwrap()
+}
+
+pub async fn mqtt_broker() -> MqttBroker {
+    MqttBroker::start(any_port(), MqttMockConfig::default())
+        .await
+        .unwrap()
+}
+
+/// A scenario from the YAML under the `scenario` key, talking to `addr`.
+pub fn scenario(addr: SocketAddr, yaml: &str) -> Scenario {
+    let mut scenario_map: Value =
+        serde_yaml::from_str(&format!("scenario:\n{}", indent(yaml))).unwrap();
+
+    let scenario = scenario_map["scenario"].as_mapping_mut().unwrap();
+    scenario.insert("host".into(), "127.0.0.1".into());
+    scenario.insert("port".into(), Value::from(addr.port()));
+
+    Scenario::from_value(scenario_map).unwrap()
+}
+
+fn indent(yaml: &str) -> String {
+    yaml.lines().map(|line| format!("  {line}\n")).collect()
+}
+
+fn group<'a>(report: &'a Report, group: Option<&str>) -> &'a GroupReport {
+    report
+        .group(group)
+        .unwrap_or_else(|| panic!("no group {group:?} in report:\n{report}"))
+}
+
+/// The count of step `index`.
+pub fn step_total(report: &Report, index: usize) -> usize {
+    group_step_total(report, None, index)
+}
+
+pub fn group_step_total(report: &Report, group_name: Option<&str>, index: usize) -> usize {
+    group(report, group_name).steps[index].count()
+}
+
+/// The average latency of step `index` in milliseconds.
+pub fn step_avg_ms(report: &Report, index: usize) -> f64 {
+    group(report, None).steps[index].raw().mean() / 1000.0
+}
+
+/// The count of metric `name`, 0 when it wasn't recorded.
+pub fn metric_total(report: &Report, name: &str) -> usize {
+    group(report, None)
+        .metric(name)
+        .map_or(0, |metric| metric.count())
+}
+
+/// The lowest latency of metric `name` in milliseconds.
+pub fn metric_min_ms(report: &Report, name: &str) -> f64 {
+    let metric = group(report, None)
+        .metric(name)
+        .unwrap_or_else(|| panic!("no metric {name} in report:\n{report}"));
+
+    metric.raw().min() as f64 / 1000.0
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{metric_total, scenario, step_total};
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::HeaderValue,
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Request, Response, Server,
+};
+
+/// A Greeter answering every call with one `HelloReply` and `status`,
+/// after `delay`.
+struct GreeterServer {
+    addr: SocketAddr,
+    /// Request headers of the calls, in order
+    calls: Arc<Mutex<Vec<HeaderMap>>>,
+}
+
+impl GreeterServer {
+    fn start(status: u32, delay: Duration) -> Self {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+
+        let received = calls.clone();
+        let make_service = make_service_fn(move |_| {
+            let received = received.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    received.lock().unwrap().push(request.headers().clone());
+                    answer(request, status, delay)
+                }))
+            }
+        });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .http2_only(true)
+            .serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Self { addr, calls }
+    }
+
+    fn calls(&self) -> Vec<HeaderMap> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+async fn answer(
+    mut request: Request<Body>,
+    status: u32,
+    delay: Duration,
+) -> Result<Response<Body>, Infallible> {
+    while request.body_mut().data().await.is_some() {}
+    tokio::time::sleep(delay).await;
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        // HelloReply { message: "hi" }, length prefixed
+        let reply = [0x0a, 2, b'h', b'i'];
+        let mut frame = vec![0, 0, 0, 0, reply.len() as u8];
+        frame.extend_from_slice(&reply);
+        sender.send_data(Bytes::from(frame)).await.unwrap();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from(status));
+        sender.send_trailers(trailers).await.unwrap();
+    });
+
+    Ok(Response::builder()
+        .header("content-type", "application/grpc")
+        .body(body)
+        .unwrap())
+}
+
+fn greeter_scenario(server: &GreeterServer, step: &str) -> loadtester_v2::Scenario {
+    scenario(
+        server.addr,
+        &format!(
+            r#"
+clients: 2
+protocol: grpc
+proto:
+  files: scenarios/protos/helloworld.proto
+testloop:
+  iterations: 3
+  steps:
+    - step:
+{step}
+"#
+        ),
+    )
+}
+
+#[tokio::test]
+async fn unary_calls_are_made_with_their_metadata() {
+    let server = GreeterServer::start(0, Duration::ZERO);
+    let scenario = greeter_scenario(
+        &server,
+        r#"
+        call: helloworld.Greeter/SayHello
+        message:
+          name: client {id}
+        metadata:
+          authorization: Bearer token-{id}"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 6, "{report}");
+    assert_eq!(metric_total(&report, "status OK"), 6);
+    assert_eq!(metric_total(&report, "method helloworld.Greeter/SayHello"), 6);
+    assert_eq!(metric_total(&report, "decode-error"), 0);
+
+    let calls = server.calls();
+    assert_eq!(calls.len(), 6);
+    assert!(calls.iter().all(|headers| {
+        headers["content-type"] == "application/grpc"
+            && headers["authorization"].to_str().unwrap().starts_with("Bearer token-")
+    }));
+}
+
+#[tokio::test]
+async fn calls_not_ending_with_ok_are_errors() {
+    let server = GreeterServer::start(5, Duration::ZERO);
+    let scenario = greeter_scenario(&server, "        call: helloworld.Greeter/SayHello");
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 0, "{report}");
+    assert_eq!(metric_total(&report, "status NOT_FOUND"), 6);
+    assert_eq!(metric_total(&report, "error NOT_FOUND"), 6);
+}
+
+#[tokio::test]
+async fn calls_end_at_their_deadline() {
+    let server = GreeterServer::start(0, Duration::from_millis(500));
+    let scenario = greeter_scenario(
+        &server,
+        r#"
+        call: helloworld.Greeter/SayHello
+        timeout: 50ms"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 0, "{report}");
+    assert_eq!(metric_total(&report, "error DEADLINE_EXCEEDED"), 6);
+    assert_eq!(server.calls()[0]["grpc-timeout"], "50m");
+
+    let method = report
+        .group(None)
+        .unwrap()
+        .metric("method helloworld.Greeter/SayHello")
+        .unwrap();
+    assert!(method.raw().max() < 400_000, "{report}");
+}
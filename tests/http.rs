@@ -0,0 +1,502 @@
+mod common;
+
+use std::convert::Infallible;
+
+use common::{
+    group_step_total, http_mock, metric_min_ms, metric_total, scenario, step_avg_ms, step_total,
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+
+#[tokio::test]
+async fn every_client_and_iteration_is_counted() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 5
+protocol: http
+testloop:
+  iterations: 4
+  steps:
+    - step:
+        endpoint: /first
+    - step:
+        endpoint: /second
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 20);
+    assert_eq!(step_total(&report, 1), 20);
+    assert_eq!(mock.requests("/first"), 20);
+    assert_eq!(mock.requests("/second"), 20);
+}
+
+#[tokio::test]
+async fn pretest_and_posttest_steps_run_once_per_client() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 3
+protocol: http
+pretest:
+  steps:
+    - step:
+        endpoint: /login/{id}
+testloop:
+  iterations: 2
+  steps:
+    - step:
+        endpoint: /
+posttest:
+  steps:
+    - step:
+        endpoint: /logout
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 6);
+    for id in 0..3 {
+        assert_eq!(mock.requests(&format!("/login/{id}")), 1);
+    }
+    assert_eq!(mock.requests("/logout"), 3);
+}
+
+#[tokio::test]
+async fn total_iterations_are_shared_by_the_clients() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 3
+protocol: http
+testloop:
+  total-iterations: 7
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 7);
+    assert_eq!(mock.requests("/"), 7);
+}
+
+#[tokio::test]
+async fn steps_are_reported_in_scenario_order() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /slow:
+    delay: 100ms
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        endpoint: /slow
+    - step:
+        endpoint: /fast
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert!(step_avg_ms(&report, 0) >= 100.0, "{report}");
+    assert!(step_avg_ms(&report, 1) < 100.0, "{report}");
+}
+
+#[tokio::test]
+async fn failed_requests_are_not_counted_as_steps() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /down:
+    error-rate: 1.0
+    error-status: 503
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        endpoint: /down
+    - step:
+        endpoint: /up
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 0);
+    assert_eq!(step_total(&report, 1), 6);
+    assert_eq!(metric_total(&report, "http-error"), 6);
+    assert_eq!(mock.requests("/down"), 6);
+}
+
+#[tokio::test]
+async fn unreachable_servers_are_counted_as_connection_errors() {
+    // A port nothing listens on once the listener is dropped
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let scenario = scenario(
+        addr,
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 0);
+    assert_eq!(metric_total(&report, "connection-error"), 6);
+}
+
+#[tokio::test]
+async fn failed_graphql_operations_are_categorized() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /down:
+    error-rate: 1.0
+    error-status: 503
+  /invalid:
+    body: '{"errors": [{"message": "unknown field"}]}'
+  /empty:
+    body: '{"data": {}}'
+  /ok:
+    body: '{"data": {"login": {"token": "secret"}}}'
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        endpoint: /down
+        graphql:
+          query: '{ health }'
+    - step:
+        endpoint: /invalid
+        graphql:
+          query: 'query Broken { nope }'
+          operation: Broken
+    - step:
+        endpoint: /empty
+        graphql:
+          query: '{ login { token } }'
+          extract:
+            token: login.token
+    - step:
+        endpoint: /ok
+        graphql:
+          query: 'mutation Login { login { token } }'
+          operation: Login
+          extract:
+            token: login.token
+    - step:
+        endpoint: /after/{token}
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    // Failed operations aren't counted as steps
+    assert_eq!(step_total(&report, 0), 0);
+    assert_eq!(step_total(&report, 1), 0);
+    assert_eq!(step_total(&report, 2), 0);
+    assert_eq!(step_total(&report, 3), 6);
+
+    assert_eq!(metric_total(&report, "http-error"), 6);
+    assert_eq!(metric_total(&report, "operation-error Broken"), 6);
+    assert_eq!(metric_total(&report, "extract-error"), 6);
+    assert_eq!(metric_total(&report, "operation Login"), 6);
+    assert_eq!(mock.requests("/after/secret"), 6);
+}
+
+#[tokio::test]
+async fn graphql_variables_are_filled_in_but_not_the_query() {
+    // Answers with errors unless the query arrives as written
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request: Request<Body>| async {
+            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            let response =
+                if body["query"] == "query{user{id}}" && body["variables"]["user"] != "user{id}" {
+                    r#"{"data": {}}"#
+                } else {
+                    r#"{"errors": [{"message": "unexpected request"}]}"#
+                };
+            Ok::<_, Infallible>(Response::new(Body::from(response)))
+        }))
+    }));
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    let scenario = scenario(
+        addr,
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 2
+  steps:
+    - step:
+        endpoint: /graphql
+        graphql:
+          query: 'query{user{id}}'
+          variables:
+            user: "user{id}"
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 4, "{report}");
+    assert_eq!(metric_total(&report, "operation-error anonymous"), 0);
+}
+
+#[tokio::test]
+async fn groups_are_reported_separately() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 5
+protocol: http
+testloop:
+  iterations: 2
+  steps:
+    - step:
+        endpoint: /browse
+groups:
+  browsing:
+    clients: 2
+  searching:
+    clients: 3
+    testloop:
+      iterations: 1
+      steps:
+        - step:
+            endpoint: /search
+        - step:
+            endpoint: /search
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    let text = report.to_string();
+    assert!(text.contains("Group browsing (http, 2 clients):"), "{text}");
+    assert!(
+        text.contains("Group searching (http, 3 clients):"),
+        "{text}"
+    );
+    assert_eq!(group_step_total(&report, Some("browsing"), 0), 4);
+    assert_eq!(group_step_total(&report, Some("searching"), 0), 3);
+    assert_eq!(group_step_total(&report, Some("searching"), 1), 3);
+    assert_eq!(mock.requests("/browse"), 4);
+    assert_eq!(mock.requests("/search"), 6);
+}
+
+#[tokio::test]
+async fn sse_events_are_counted_with_their_latencies() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /events:
+    delay: 50ms
+    events:
+      count: 4
+      interval: 20ms
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 2
+  steps:
+    - step:
+        endpoint: /events
+        sse: {}
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 4, "{report}");
+    assert_eq!(metric_total(&report, "event"), 16);
+    assert_eq!(metric_total(&report, "time-to-first-event"), 4);
+    assert_eq!(metric_total(&report, "event-gap"), 12);
+    assert!(metric_min_ms(&report, "time-to-first-event") >= 49.0, "{report}");
+    assert!(metric_min_ms(&report, "event-gap") >= 19.0, "{report}");
+}
+
+#[tokio::test]
+async fn sse_steps_end_at_their_event_count_type_or_duration() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /events:
+    events:
+      count: 6
+      event: [tick, tock]
+  /endless:
+    events:
+      count: 1000
+      interval: 20ms
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 1
+  steps:
+    - step:
+        endpoint: /events
+        sse:
+          events: 2
+    - step:
+        endpoint: /events
+        sse:
+          event: tock
+    - step:
+        endpoint: /endless
+        sse:
+          duration: 100ms
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 2, "{report}");
+    assert_eq!(step_total(&report, 1), 2);
+    assert_eq!(step_total(&report, 2), 2);
+
+    // 2 and 3 per client, and one every 20ms for 100ms
+    let endless = metric_total(&report, "event") - 2 * (2 + 3);
+    assert!((6..=12).contains(&endless), "{report}");
+}
+
+#[tokio::test]
+async fn long_polls_record_how_long_they_were_held() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /poll:
+    delay: 40ms
+  /stuck:
+    delay: 500ms
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 2
+  steps:
+    - step:
+        endpoint: /poll
+        long-poll:
+          polls: 3
+    - step:
+        endpoint: /stuck
+        long-poll:
+          polls: 2
+          timeout: 30ms
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 4, "{report}");
+    assert_eq!(metric_total(&report, "poll-held"), 12);
+    assert!(metric_min_ms(&report, "poll-held") >= 39.0, "{report}");
+    assert_eq!(mock.requests("/poll"), 12);
+
+    // Polls held past the timeout are issued again
+    assert_eq!(metric_total(&report, "poll-timeout"), 8);
+    assert_eq!(mock.requests("/stuck"), 8);
+}
+
+#[tokio::test]
+async fn graphql_operations_record_their_latency() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /graphql:
+    delay: 30ms
+    body: '{"data": {"user": {"id": "1"}}}'
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+protocol: http
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        endpoint: /graphql
+        graphql:
+          query: 'query User { user { id } }'
+          operation: User
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 6, "{report}");
+    assert_eq!(metric_total(&report, "operation User"), 6);
+    assert!(metric_min_ms(&report, "operation User") >= 29.0, "{report}");
+    assert_eq!(mock.requests("/graphql"), 6);
+}
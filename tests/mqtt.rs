@@ -0,0 +1,204 @@
+mod common;
+
+use common::{metric_total, mqtt_broker, scenario, step_total};
+
+#[tokio::test]
+async fn published_messages_are_received_and_awaited() {
+    let broker = mqtt_broker().await;
+    let scenario = scenario(
+        broker.local_addr(),
+        r#"
+clients: 3
+protocol: mqtt
+client-id: test_client_{id}
+credentials:
+  username: user
+  password: password
+pretest:
+  steps:
+    - step:
+        subscribe: echo/{id}/#
+        qos: 1
+testloop:
+  iterations: 5
+  steps:
+    - step:
+        publish: echo/{id}/message
+        qos: 1
+    - step:
+        await: echo/{id}/#
+        timeout: 5s
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 15);
+    assert_eq!(step_total(&report, 1), 15);
+    assert_eq!(broker.publishes(), 15);
+
+    let received: usize = (0..3)
+        .map(|id| metric_total(&report, &format!("received echo/{id}/#")))
+        .sum();
+    assert_eq!(received, 15, "{report}");
+}
+
+#[tokio::test]
+async fn shared_subscriptions_receive_each_message_once() {
+    let broker = mqtt_broker().await;
+    let scenario = scenario(
+        broker.local_addr(),
+        r#"
+clients: 4
+protocol: mqtt
+client-id: shared_client_{id}
+credentials:
+  username: user
+  password: password
+pretest:
+  steps:
+    - step:
+        subscribe: $share/workers/jobs/+
+        qos: 2
+testloop:
+  iterations: 5
+  interval: 10ms
+  steps:
+    - step:
+        publish: jobs/{id}
+        qos: 2
+posttest:
+  steps:
+    - step:
+        unsubscribe: $share/workers/jobs/+
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 20);
+    assert_eq!(broker.publishes(), 20);
+
+    // One member gets each message, not all four. The broker sends it on
+    // before completing the publish, and ahead of the member's unsubscribe
+    // ack, which the posttest waits for.
+    let received = metric_total(&report, "received $share/workers/jobs/+");
+    assert_eq!(received, 20, "{report}");
+}
+
+#[tokio::test]
+async fn awaits_ignore_messages_from_before_the_iteration() {
+    let broker = mqtt_broker().await;
+    let scenario = scenario(
+        broker.local_addr(),
+        r#"
+clients: 1
+protocol: mqtt
+client-id: stale_client_{id}
+credentials:
+  username: user
+  password: password
+pretest:
+  steps:
+    - step:
+        subscribe: stale/{id}
+        qos: 1
+    - step:
+        publish: stale/{id}
+        payload: early
+        qos: 1
+    - step:
+        publish: stale/{id}
+        payload: early
+        qos: 1
+testloop:
+  iterations: 2
+  interval: 100ms
+  steps:
+    - step:
+        await: stale/{id}
+        timeout: 100ms
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    // Both messages arrived before the first iteration started. They carry
+    // no send time, so they are counted without a latency.
+    assert_eq!(step_total(&report, 0), 0, "{report}");
+    assert_eq!(metric_total(&report, "received stale/0"), 2, "{report}");
+}
+
+#[tokio::test]
+async fn awaits_keep_messages_for_later_awaits() {
+    let broker = mqtt_broker().await;
+    let scenario = scenario(
+        broker.local_addr(),
+        r#"
+clients: 2
+protocol: mqtt
+client-id: order_client_{id}
+credentials:
+  username: user
+  password: password
+pretest:
+  steps:
+    - step:
+        subscribe: order/{id}/#
+        qos: 1
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        publish: order/{id}/b
+        qos: 1
+    - step:
+        publish: order/{id}/a
+        qos: 1
+    - step:
+        await: order/{id}/a
+        timeout: 1s
+    - step:
+        await: order/{id}/b
+        timeout: 1s
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    // The message on b arrives while the first await waits for a
+    assert_eq!(step_total(&report, 2), 6, "{report}");
+    assert_eq!(step_total(&report, 3), 6, "{report}");
+}
+
+#[tokio::test]
+async fn reconnect_storms_reconnect_the_first_clients() {
+    let broker = mqtt_broker().await;
+    let scenario = scenario(
+        broker.local_addr(),
+        r#"
+clients: 3
+duration: 600ms
+protocol: mqtt
+client-id: storm_client_{id}
+reconnect-storm:
+  clients: 2
+  at: 100ms
+  offline: 100ms
+  mode: drop
+testloop:
+  interval: 20ms
+  steps:
+    - step:
+        publish: storm/{id}
+        qos: 1
+        timeout: 2s
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(metric_total(&report, "reconnect"), 2, "{report}");
+    // Publishes queued while offline reach the broker after the reconnect
+    assert!(broker.publishes() >= step_total(&report, 0), "{report}");
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use common::{metric_min_ms, metric_total, scenario, step_avg_ms, step_total};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Answers each write with `+OK` per command in it, after `delay`.
+async fn slow_redis(delay: Duration) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(answer(stream, delay));
+        }
+    });
+
+    addr
+}
+
+async fn answer(mut stream: TcpStream, delay: Duration) {
+    let mut buffer = [0; 4096];
+
+    while let Ok(read @ 1..) = stream.read(&mut buffer).await {
+        // Commands are arrays, their arguments bulk strings
+        let commands = String::from_utf8_lossy(&buffer[..read])
+            .split("\r\n")
+            .filter(|line| line.starts_with('*'))
+            .count();
+
+        tokio::time::sleep(delay).await;
+        if stream.write_all(&b"+OK\r\n".repeat(commands)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn pipelined_commands_are_timed_from_the_write() {
+    let addr = slow_redis(Duration::from_millis(50)).await;
+    let scenario = scenario(
+        addr,
+        r#"
+clients: 2
+protocol: redis
+testloop:
+  iterations: 2
+  steps:
+    - step:
+        command: SET key:{id}:{n} value
+        pipeline: 5
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 4, "{report}");
+    assert_eq!(metric_total(&report, "command SET"), 20);
+    // The replies arrive together, none of them sooner than the delay
+    assert!(metric_min_ms(&report, "command SET") >= 49.0, "{report}");
+    assert!(step_avg_ms(&report, 0) >= 49.0);
+}
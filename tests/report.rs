@@ -0,0 +1,59 @@
+mod common;
+
+use std::{path::PathBuf, time::Duration};
+
+use common::http_mock;
+use loadtester_v2::{HttpStep, Report, Scenario};
+
+/// A path in the temp directory, unique to the test.
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("loadtester-{}-{name}", std::process::id()))
+}
+
+#[tokio::test]
+async fn reports_are_exported_to_the_report_file() {
+    let mock = http_mock("{}").await;
+    let path = temp_file("report.json");
+    let scenario = Scenario::builder()
+        .protocol("http")
+        .host("127.0.0.1")
+        .port(mock.local_addr().port())
+        .clients(2)
+        .iterations(3)
+        .step(HttpStep::get("/"))
+        .report_file(path.to_str().unwrap())
+        .build()
+        .unwrap();
+
+    scenario.run().await;
+
+    let exported: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(exported["aborted"], false);
+    let step = &exported["groups"][0]["steps"][0];
+    assert_eq!(step["step"]["endpoint"], "/");
+    assert_eq!(step["count"], 6);
+    assert!(step["raw"]["p99"].as_f64().unwrap() > 0.0);
+}
+
+#[test]
+fn aborted_reports_are_exported_as_such() {
+    let path = temp_file("aborted.yml");
+    let report = Report {
+        elapsed: Duration::from_millis(1500),
+        aborted: true,
+        arrivals: None,
+        groups: Vec::new(),
+    };
+
+    report.export(&path).unwrap();
+
+    let exported: serde_yaml::Value =
+        serde_yaml::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(exported["aborted"].as_bool(), Some(true));
+    assert_eq!(exported["elapsed-ms"].as_u64(), Some(1500));
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{http_mock, scenario, step_total};
+
+#[tokio::test]
+async fn duration_ends_the_run() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+duration: 1s
+grace-period: 1s
+protocol: http
+testloop:
+  interval: 20ms
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let start = Instant::now();
+    let report = scenario.run().await;
+    let elapsed = start.elapsed();
+
+    assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(3), "{elapsed:?}");
+    assert!(step_total(&report, 0) > 0, "{report}");
+}
+
+#[tokio::test]
+async fn iterations_end_the_run_before_the_duration() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 2
+duration: 1m
+protocol: http
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let start = Instant::now();
+    let report = scenario.run().await;
+
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(step_total(&report, 0), 6);
+}
+
+#[tokio::test]
+async fn interval_spaces_out_iterations() {
+    let mock = http_mock("{}").await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 1
+protocol: http
+testloop:
+  iterations: 4
+  interval: 100ms
+  steps:
+    - step:
+        endpoint: /
+"#,
+    );
+
+    let start = Instant::now();
+    let report = scenario.run().await;
+
+    // The interval is waited before each iteration, the first one too
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(step_total(&report, 0), 4);
+}
+
+#[tokio::test]
+async fn slow_steps_in_flight_at_the_end_are_interrupted() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /slow:
+    delay: 5s
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 3
+duration: 500ms
+grace-period: 100ms
+protocol: http
+testloop:
+  steps:
+    - step:
+        endpoint: /slow
+"#,
+    );
+
+    let start = Instant::now();
+    let report = scenario.run().await;
+
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert_eq!(step_total(&report, 0), 0);
+    assert_eq!(report.groups[0].steps[0].interrupted(), 3, "{report}");
+}
+
+#[tokio::test]
+async fn no_step_starts_once_the_client_is_stopped() {
+    let mock = http_mock(
+        r#"
+endpoints:
+  /slow:
+    delay: 300ms
+"#,
+    )
+    .await;
+    let scenario = scenario(
+        mock.local_addr(),
+        r#"
+clients: 1
+duration: 200ms
+grace-period: 1s
+protocol: http
+testloop:
+  steps:
+    - step:
+        endpoint: /slow
+    - step:
+        endpoint: /slow
+    - step:
+        endpoint: /slow
+    - step:
+        endpoint: /slow
+    - step:
+        endpoint: /slow
+"#,
+    );
+
+    let start = Instant::now();
+    let report = scenario.run().await;
+
+    // The step in flight at the stop finishes within the grace period, the
+    // rest of the iteration is skipped
+    assert!(start.elapsed() < Duration::from_secs(1), "{report}");
+    assert_eq!(step_total(&report, 0), 1, "{report}");
+    for step in 1..5 {
+        assert_eq!(step_total(&report, step), 0, "{report}");
+    }
+    assert_eq!(mock.requests("/slow"), 1);
+}
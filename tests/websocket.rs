@@ -0,0 +1,156 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::{metric_total, scenario, step_total};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A websocket server echoing text messages. `delay <text>` is answered with
+/// `<text>` after a moment, without reading what arrives meanwhile.
+struct EchoServer {
+    addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
+}
+
+impl EchoServer {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(echo(stream));
+            }
+        });
+
+        Self { addr, connections }
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn echo(stream: tokio::net::TcpStream) {
+    let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+        return;
+    };
+
+    while let Some(Ok(message)) = socket.next().await {
+        let Message::Text(text) = message else {
+            continue;
+        };
+
+        let reply = match text.strip_prefix("delay ") {
+            Some(text) => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                text.to_owned()
+            }
+            None => text,
+        };
+
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn sent_messages_are_awaited_per_client() {
+    let server = EchoServer::start().await;
+    let scenario = scenario(
+        server.addr,
+        r#"
+clients: 3
+protocol: websocket
+testloop:
+  iterations: 4
+  steps:
+    - step:
+        send: hello {id}
+    - step:
+        await: ^hello {id}$
+        timeout: 1s
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 0), 12);
+    assert_eq!(step_total(&report, 1), 12, "{report}");
+    assert_eq!(metric_total(&report, "message-sent"), 12);
+    assert_eq!(server.connections(), 3);
+}
+
+#[tokio::test]
+async fn messages_arriving_before_the_pong_are_left_for_the_next_await() {
+    let server = EchoServer::start().await;
+    let scenario = scenario(
+        server.addr,
+        r#"
+clients: 2
+protocol: websocket
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        send: delay reply {id}
+    - step:
+        ping: hi
+        timeout: 1s
+    - step:
+        await: ^reply {id}$
+        timeout: 1s
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 1), 6, "{report}");
+    assert_eq!(step_total(&report, 2), 6, "{report}");
+    assert_eq!(metric_total(&report, "message-received"), 6);
+}
+
+#[tokio::test]
+async fn steps_after_a_close_reconnect() {
+    let server = EchoServer::start().await;
+    let scenario = scenario(
+        server.addr,
+        r#"
+clients: 2
+protocol: websocket
+testloop:
+  iterations: 3
+  steps:
+    - step:
+        send: hello
+    - step:
+        await: hello
+        timeout: 1s
+    - step:
+        close: true
+"#,
+    );
+
+    let report = scenario.run().await;
+
+    assert_eq!(step_total(&report, 1), 6, "{report}");
+    assert_eq!(step_total(&report, 2), 6);
+    assert_eq!(server.connections(), 6);
+
+    let connect = report.group(None).unwrap().metric("connect").unwrap();
+    assert_eq!(connect.count(), 6);
+    assert_eq!(connect.raw().len(), 6);
+}